@@ -0,0 +1,269 @@
+use std::fmt::{self, Display};
+
+use crate::position::{Move, Position};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct EpdError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl Display for EpdError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for EpdError {}
+
+// One EPD line: a position followed by "opcode operand ...;" operations
+#[derive(Clone)]
+pub struct EpdRecord {
+    pub position: Position,
+    pub operations: Vec<(String, Vec<String>)>,
+}
+
+impl EpdRecord {
+    pub fn parse(text: &str, line: usize) -> Result<Self, EpdError> {
+        let error = |message: String| EpdError { line, message };
+        let mut rest = text;
+        let mut fen_fields = vec![];
+        for _ in 0..4 {
+            rest = rest.trim_start();
+            let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+            if end == 0 {
+                return Err(error("expected 4 position fields".to_string()));
+            }
+            fen_fields.push(&rest[..end]);
+            rest = &rest[end..];
+        }
+        let mut position = match Position::from_fen(&fen_fields.join(" ")) {
+            Ok(position) => position,
+            Err(e) => return Err(error(e.to_string())),
+        };
+        let operations = parse_operations(rest).map_err(error)?;
+
+        for (opcode, operands) in &operations {
+            let number = || -> Result<u32, EpdError> {
+                match operands.first().map(|operand| operand.parse()) {
+                    Some(Ok(number)) => Ok(number),
+                    _ => Err(error(format!("'{}' expects a number", opcode))),
+                }
+            };
+            match opcode.as_str() {
+                "hmvc" => position.halfmove_clock = number()?,
+                "fmvn" => position.fullmove_number = number()?,
+                "dm" => {
+                    number()?;
+                },
+                "ce" => match operands.first().map(|operand| operand.parse::<i32>()) {
+                    Some(Ok(_)) => (),
+                    _ => return Err(error("'ce' expects a number".to_string())),
+                },
+                "bm" | "am" => {
+                    for san in operands {
+                        if position.parse_san(san).is_none() {
+                            return Err(error(format!("illegal or ambiguous move '{}' in '{}'", san, opcode)));
+                        }
+                    }
+                },
+                _ => (),
+            }
+        }
+
+        Ok(EpdRecord { position, operations })
+    }
+
+    pub fn operation(&self, opcode: &str) -> Option<&[String]> {
+        self.operations.iter().find(|(name, _)| name == opcode).map(|(_, operands)| operands.as_slice())
+    }
+
+    fn moves(&self, opcode: &str) -> Vec<Move> {
+        // The moves are checked in parse
+        self.operation(opcode).unwrap_or_default().iter().filter_map(|san| self.position.parse_san(san)).collect()
+    }
+
+    pub fn best_moves(&self) -> Vec<Move> {
+        self.moves("bm")
+    }
+
+    pub fn avoid_moves(&self) -> Vec<Move> {
+        self.moves("am")
+    }
+
+    pub fn id(&self) -> Option<&str> {
+        self.operation("id").and_then(|operands| operands.first()).map(|id| id.as_str())
+    }
+
+    // Comments c0 to c9
+    pub fn comment(&self, n: usize) -> Option<&str> {
+        self.operation(&format!("c{}", n)).and_then(|operands| operands.first()).map(|comment| comment.as_str())
+    }
+
+    // Number of moves of the side to move before mate
+    pub fn direct_mate(&self) -> Option<u32> {
+        self.operation("dm").and_then(|operands| operands.first()).and_then(|operand| operand.parse().ok())
+    }
+
+    // Centipawn evaluation from the point of view of the side to move
+    pub fn centipawn_eval(&self) -> Option<i32> {
+        self.operation("ce").and_then(|operands| operands.first()).and_then(|operand| operand.parse().ok())
+    }
+
+    pub fn to_epd(&self) -> String {
+        let fen = self.position.to_fen();
+        let mut epd: String = fen.split(' ').take(4).collect::<Vec<&str>>().join(" ");
+        for (opcode, operands) in &self.operations {
+            epd.push(' ');
+            epd.push_str(opcode);
+            for operand in operands {
+                if operand.is_empty() || operand.contains([' ', ';', '"']) {
+                    epd.push_str(&format!(" \"{}\"", operand.replace('"', "\\\"")));
+                } else {
+                    epd.push(' ');
+                    epd.push_str(operand);
+                }
+            }
+            epd.push(';');
+        }
+        epd
+    }
+}
+
+fn parse_operations(text: &str) -> Result<Vec<(String, Vec<String>)>, String> {
+    let mut operations = vec![];
+    let mut chars = text.chars().peekable();
+    let mut words: Vec<String> = vec![];
+
+    loop {
+        while chars.peek().is_some_and(|c| c.is_whitespace()) {
+            chars.next();
+        }
+        match chars.next() {
+            None => break,
+            Some(';') => {
+                if words.is_empty() {
+                    return Err("empty operation".to_string());
+                }
+                let opcode = words.remove(0);
+                operations.push((opcode, std::mem::take(&mut words)));
+            },
+            Some('"') => {
+                if words.is_empty() {
+                    return Err("operation without an opcode".to_string());
+                }
+                let mut operand = String::new();
+                loop {
+                    match chars.next() {
+                        Some('\\') => operand.extend(chars.next()),
+                        Some('"') => break,
+                        Some(c) => operand.push(c),
+                        None => return Err("unterminated string".to_string()),
+                    }
+                }
+                words.push(operand);
+            },
+            Some(c) => {
+                let mut word = c.to_string();
+                while let Some(&c) = chars.peek() {
+                    if c.is_whitespace() || c == ';' {
+                        break;
+                    }
+                    word.push(c);
+                    chars.next();
+                }
+                words.push(word);
+            },
+        }
+    }
+
+    if !words.is_empty() {
+        return Err(format!("operation '{}' is missing its ';'", words[0]));
+    }
+    Ok(operations)
+}
+
+// Every non empty line of an EPD file, each one parsed on its own
+pub fn parse_epd(text: &str) -> Vec<Result<EpdRecord, EpdError>> {
+    text.lines().enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| EpdRecord::parse(line, i + 1))
+        .collect()
+}
+
+#[derive(Debug, Clone)]
+pub struct TestResult {
+    pub id: String,
+    pub found: Option<String>,
+    // None when the record has neither bm nor am to check against
+    pub passed: Option<bool>,
+}
+
+impl Display for TestResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let verdict = match self.passed {
+            Some(true) => "pass",
+            Some(false) => "FAIL",
+            None => "skip",
+        };
+        write!(f, "{}: {} ({})", self.id, self.found.as_deref().unwrap_or("no move"), verdict)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SuiteReport {
+    pub results: Vec<TestResult>,
+}
+
+impl SuiteReport {
+    pub fn passed(&self) -> usize {
+        self.results.iter().filter(|result| result.passed == Some(true)).count()
+    }
+
+    pub fn failed(&self) -> usize {
+        self.results.iter().filter(|result| result.passed == Some(false)).count()
+    }
+
+    pub fn skipped(&self) -> usize {
+        self.results.iter().filter(|result| result.passed.is_none()).count()
+    }
+}
+
+impl Display for SuiteReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} passed, {} failed, {} skipped out of {}", self.passed(), self.failed(), self.skipped(), self.results.len())
+    }
+}
+
+// Search the record's position and check the move found against its bm and am operations.
+// The search is given by the caller, with its own depth or time limit.
+pub fn run_test<F>(record: &EpdRecord, index: usize, search: F) -> TestResult where F: FnOnce(&Position) -> Option<Move> {
+    let found = search(&record.position);
+    let best_moves = record.best_moves();
+    let avoid_moves = record.avoid_moves();
+    let passed = if best_moves.is_empty() && avoid_moves.is_empty() {
+        None
+    } else {
+        Some(found.is_some_and(|mv| (best_moves.is_empty() || best_moves.contains(&mv)) && !avoid_moves.contains(&mv)))
+    };
+    TestResult {
+        id: record.id().map(|id| id.to_string()).unwrap_or_else(|| format!("#{}", index + 1)),
+        found: found.map(|mv| record.position.san(mv)),
+        passed,
+    }
+}
+
+// Run every record through run_test, calling on_result as soon as each one is done
+pub fn run_suite<F, G>(records: &[EpdRecord], mut search: F, mut on_result: G) -> SuiteReport
+where
+    F: FnMut(&Position) -> Option<Move>,
+    G: FnMut(&TestResult),
+{
+    let mut report = SuiteReport::default();
+    for (i, record) in records.iter().enumerate() {
+        let result = run_test(record, i, &mut search);
+        on_result(&result);
+        report.results.push(result);
+    }
+    report
+}
@@ -0,0 +1,511 @@
+use std::fmt::{self, Display};
+use std::ops::{Add, AddAssign, Mul, Neg, Sub, SubAssign};
+
+use crate::position::Position;
+use crate::{Board, Color, PieceType, BISHOP_VECS, KNIGHT_VECS, ROOK_VECS};
+
+// Plain material values, for exchanges and move ordering
+pub fn piece_value(piece_type: PieceType) -> i32 {
+    match piece_type {
+        PieceType::Pawn => 100,
+        PieceType::Knight => 320,
+        PieceType::Bishop => 330,
+        PieceType::Rook => 500,
+        PieceType::Queen => 900,
+        PieceType::King => 0,
+    }
+}
+
+// A middlegame and an endgame value, blended by how much material is left on the board
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Score {
+    pub mg: i32,
+    pub eg: i32,
+}
+
+const fn s(mg: i32, eg: i32) -> Score {
+    Score { mg, eg }
+}
+
+// Phase of a position with all the pieces, down to 0 with only kings and pawns
+pub const MAX_PHASE: i32 = 24;
+
+impl Score {
+    pub fn taper(self, phase: i32) -> i32 {
+        (self.mg * phase + self.eg * (MAX_PHASE - phase)) / MAX_PHASE
+    }
+}
+
+impl Add for Score {
+    type Output = Score;
+    fn add(self, other: Score) -> Score {
+        s(self.mg + other.mg, self.eg + other.eg)
+    }
+}
+
+impl Sub for Score {
+    type Output = Score;
+    fn sub(self, other: Score) -> Score {
+        s(self.mg - other.mg, self.eg - other.eg)
+    }
+}
+
+impl Neg for Score {
+    type Output = Score;
+    fn neg(self) -> Score {
+        s(-self.mg, -self.eg)
+    }
+}
+
+impl Mul<i32> for Score {
+    type Output = Score;
+    fn mul(self, factor: i32) -> Score {
+        s(self.mg * factor, self.eg * factor)
+    }
+}
+
+impl AddAssign for Score {
+    fn add_assign(&mut self, other: Score) {
+        *self = *self + other;
+    }
+}
+
+impl SubAssign for Score {
+    fn sub_assign(&mut self, other: Score) {
+        *self = *self - other;
+    }
+}
+
+// Bonus for each square from white's point of view, a8 first so that the tables read like a board
+const PAWN_TABLE: [i32; 64] = [
+     0,  0,  0,  0,  0,  0,  0,  0,
+    50, 50, 50, 50, 50, 50, 50, 50,
+    10, 10, 20, 30, 30, 20, 10, 10,
+     5,  5, 10, 25, 25, 10,  5,  5,
+     0,  0,  0, 20, 20,  0,  0,  0,
+     5, -5,-10,  0,  0,-10, -5,  5,
+     5, 10, 10,-20,-20, 10, 10,  5,
+     0,  0,  0,  0,  0,  0,  0,  0,
+];
+
+const KNIGHT_TABLE: [i32; 64] = [
+    -50,-40,-30,-30,-30,-30,-40,-50,
+    -40,-20,  0,  0,  0,  0,-20,-40,
+    -30,  0, 10, 15, 15, 10,  0,-30,
+    -30,  5, 15, 20, 20, 15,  5,-30,
+    -30,  0, 15, 20, 20, 15,  0,-30,
+    -30,  5, 10, 15, 15, 10,  5,-30,
+    -40,-20,  0,  5,  5,  0,-20,-40,
+    -50,-40,-30,-30,-30,-30,-40,-50,
+];
+
+const BISHOP_TABLE: [i32; 64] = [
+    -20,-10,-10,-10,-10,-10,-10,-20,
+    -10,  0,  0,  0,  0,  0,  0,-10,
+    -10,  0,  5, 10, 10,  5,  0,-10,
+    -10,  5,  5, 10, 10,  5,  5,-10,
+    -10,  0, 10, 10, 10, 10,  0,-10,
+    -10, 10, 10, 10, 10, 10, 10,-10,
+    -10,  5,  0,  0,  0,  0,  5,-10,
+    -20,-10,-10,-10,-10,-10,-10,-20,
+];
+
+const ROOK_TABLE: [i32; 64] = [
+     0,  0,  0,  0,  0,  0,  0,  0,
+     5, 10, 10, 10, 10, 10, 10,  5,
+    -5,  0,  0,  0,  0,  0,  0, -5,
+    -5,  0,  0,  0,  0,  0,  0, -5,
+    -5,  0,  0,  0,  0,  0,  0, -5,
+    -5,  0,  0,  0,  0,  0,  0, -5,
+    -5,  0,  0,  0,  0,  0,  0, -5,
+     0,  0,  0,  5,  5,  0,  0,  0,
+];
+
+const QUEEN_TABLE: [i32; 64] = [
+    -20,-10,-10, -5, -5,-10,-10,-20,
+    -10,  0,  0,  0,  0,  0,  0,-10,
+    -10,  0,  5,  5,  5,  5,  0,-10,
+     -5,  0,  5,  5,  5,  5,  0, -5,
+      0,  0,  5,  5,  5,  5,  0, -5,
+    -10,  5,  5,  5,  5,  5,  0,-10,
+    -10,  0,  5,  0,  0,  0,  0,-10,
+    -20,-10,-10, -5, -5,-10,-10,-20,
+];
+
+const KING_TABLE: [i32; 64] = [
+    -30,-40,-40,-50,-50,-40,-40,-30,
+    -30,-40,-40,-50,-50,-40,-40,-30,
+    -30,-40,-40,-50,-50,-40,-40,-30,
+    -30,-40,-40,-50,-50,-40,-40,-30,
+    -20,-30,-30,-40,-40,-30,-30,-20,
+    -10,-20,-20,-20,-20,-20,-20,-10,
+     20, 20,  0,  0,  0,  0, 20, 20,
+     20, 30, 10,  0,  0, 10, 30, 20,
+];
+
+const PAWN_ENDGAME_TABLE: [i32; 64] = [
+     0,  0,  0,  0,  0,  0,  0,  0,
+    50, 50, 50, 50, 50, 50, 50, 50,
+    30, 30, 30, 30, 30, 30, 30, 30,
+    15, 15, 15, 15, 15, 15, 15, 15,
+     5,  5,  5,  5,  5,  5,  5,  5,
+     0,  0,  0,  0,  0,  0,  0,  0,
+     0,  0,  0,  0,  0,  0,  0,  0,
+     0,  0,  0,  0,  0,  0,  0,  0,
+];
+
+const KING_ENDGAME_TABLE: [i32; 64] = [
+    -50,-40,-30,-20,-20,-30,-40,-50,
+    -30,-20,-10,  0,  0,-10,-20,-30,
+    -30,-10, 20, 30, 30, 20,-10,-30,
+    -30,-10, 30, 40, 40, 30,-10,-30,
+    -30,-10, 30, 40, 40, 30,-10,-30,
+    -30,-10, 20, 30, 30, 20,-10,-30,
+    -30,-30,  0,  0,  0,  0,-30,-30,
+    -50,-30,-30,-30,-30,-30,-30,-50,
+];
+
+// Every weight of the evaluation in one place, so that they can be tuned together.
+// Per piece arrays are indexed pawn, knight, bishop, rook, queen then king.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EvalParams {
+    pub material: [Score; 6],
+    pub pst_mg: [[i32; 64]; 6],
+    pub pst_eg: [[i32; 64]; 6],
+    // What each piece counts for in the game phase
+    pub phase: [i32; 6],
+    pub doubled_pawn: Score,
+    pub isolated_pawn: Score,
+    pub backward_pawn: Score,
+    // By rank counted from the pawn's own side
+    pub passed_pawn: [Score; 8],
+    // Per square a knight, bishop, rook or queen can go to without being taken by a pawn
+    pub mobility: [Score; 4],
+    // Per pawn in front of the king, and per file next to it without one
+    pub pawn_shield: Score,
+    pub king_open_file: Score,
+    // Per square next to the king attacked by an enemy knight, bishop, rook or queen
+    pub king_attack: [Score; 4],
+    pub bishop_pair: Score,
+    pub rook_open_file: Score,
+    pub rook_semi_open_file: Score,
+}
+
+pub const DEFAULT_PARAMS: EvalParams = EvalParams {
+    material: [s(82, 94), s(337, 281), s(365, 297), s(477, 512), s(1025, 936), s(0, 0)],
+    pst_mg: [PAWN_TABLE, KNIGHT_TABLE, BISHOP_TABLE, ROOK_TABLE, QUEEN_TABLE, KING_TABLE],
+    pst_eg: [PAWN_ENDGAME_TABLE, KNIGHT_TABLE, BISHOP_TABLE, ROOK_TABLE, QUEEN_TABLE, KING_ENDGAME_TABLE],
+    phase: [0, 1, 1, 2, 4, 0],
+    doubled_pawn: s(-10, -20),
+    isolated_pawn: s(-10, -15),
+    backward_pawn: s(-8, -10),
+    passed_pawn: [s(0, 0), s(0, 5), s(5, 10), s(10, 20), s(20, 40), s(35, 70), s(60, 110), s(0, 0)],
+    mobility: [s(4, 4), s(5, 5), s(2, 4), s(1, 2)],
+    pawn_shield: s(12, 0),
+    king_open_file: s(-20, 0),
+    king_attack: [s(-8, 0), s(-6, 0), s(-8, 0), s(-12, 0)],
+    bishop_pair: s(30, 50),
+    rook_open_file: s(25, 10),
+    rook_semi_open_file: s(12, 6),
+};
+
+impl Default for EvalParams {
+    fn default() -> Self {
+        DEFAULT_PARAMS
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Term {
+    Material,
+    PieceSquares,
+    Pawns,
+    Mobility,
+    KingSafety,
+    BishopPair,
+    Rooks,
+}
+
+impl Term {
+    pub const ALL: [Term; 7] = [Term::Material, Term::PieceSquares, Term::Pawns, Term::Mobility, Term::KingSafety, Term::BishopPair, Term::Rooks];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Term::Material => "Material",
+            Term::PieceSquares => "Piece squares",
+            Term::Pawns => "Pawns",
+            Term::Mobility => "Mobility",
+            Term::KingSafety => "King safety",
+            Term::BishopPair => "Bishop pair",
+            Term::Rooks => "Rooks",
+        }
+    }
+}
+
+// What each term of the evaluation is worth to each side, before tapering
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct EvalTrace {
+    // Indexed by term, then white and black
+    pub terms: [[Score; 2]; Term::ALL.len()],
+    pub phase: i32,
+}
+
+fn color_index(color: Color) -> usize {
+    match color {
+        Color::White => 0,
+        Color::Black => 1,
+    }
+}
+
+impl EvalTrace {
+    pub fn get(&self, term: Term, color: Color) -> Score {
+        self.terms[term as usize][color_index(color)]
+    }
+
+    fn add(&mut self, term: Term, color: Color, score: Score) {
+        self.terms[term as usize][color_index(color)] += score;
+    }
+
+    pub fn total(&self, color: Color) -> Score {
+        self.terms.iter().fold(Score::default(), |total, term| total + term[color_index(color)])
+    }
+
+    // Tapered, from white's point of view
+    pub fn score(&self) -> i32 {
+        (self.total(Color::White) - self.total(Color::Black)).taper(self.phase)
+    }
+}
+
+impl Display for EvalTrace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let row = |f: &mut fmt::Formatter<'_>, name: &str, white: Score, black: Score| {
+            let total = white - black;
+            writeln!(f, "{:<14}{:>7}{:>7}{:>9}{:>7}{:>9}{:>7}", name, white.mg, white.eg, black.mg, black.eg, total.mg, total.eg)
+        };
+        writeln!(f, "{:<14}{:>14}{:>16}{:>16}", "", "White", "Black", "Total")?;
+        writeln!(f, "{:<14}{:>7}{:>7}{:>9}{:>7}{:>9}{:>7}", "Term", "MG", "EG", "MG", "EG", "MG", "EG")?;
+        for term in Term::ALL {
+            row(f, term.name(), self.get(term, Color::White), self.get(term, Color::Black))?;
+        }
+        row(f, "Total", self.total(Color::White), self.total(Color::Black))?;
+        write!(f, "Phase {}/{}, evaluation {:+.2} for white", self.phase, MAX_PHASE, self.score() as f64 / 100.0)
+    }
+}
+
+fn piece_index(piece_type: PieceType) -> usize {
+    match piece_type {
+        PieceType::Pawn => 0,
+        PieceType::Knight => 1,
+        PieceType::Bishop => 2,
+        PieceType::Rook => 3,
+        PieceType::Queen => 4,
+        PieceType::King => 5,
+    }
+}
+
+fn square_bonus(table: &[i32; 64], color: Color, pos: (usize, usize)) -> i32 {
+    let row = match color {
+        Color::White => 7 - pos.1,
+        Color::Black => pos.1,
+    };
+    table[8 * row + pos.0]
+}
+
+// Rank counted from the color's own side
+fn relative_rank(color: Color, rank: usize) -> usize {
+    match color {
+        Color::White => rank,
+        Color::Black => 7 - rank,
+    }
+}
+
+fn forward(color: Color) -> isize {
+    match color {
+        Color::White => 1,
+        Color::Black => -1,
+    }
+}
+
+fn on_board(pos: (isize, isize)) -> Option<(usize, usize)> {
+    ((0..8).contains(&pos.0) && (0..8).contains(&pos.1)).then_some((pos.0 as usize, pos.1 as usize))
+}
+
+fn is_piece(board: &Board, pos: (usize, usize), piece_type: PieceType, color: Color) -> bool {
+    matches!(board[pos.0][pos.1], Some(piece) if piece.piece_type == piece_type && piece.color == color)
+}
+
+fn attacked_by_pawn(board: &Board, pos: (usize, usize), color: Color) -> bool {
+    [-1, 1].into_iter().filter_map(|side| on_board((pos.0 as isize + side, pos.1 as isize - forward(color))))
+        .any(|from| is_piece(board, from, PieceType::Pawn, color))
+}
+
+// Squares a knight, bishop, rook or queen attacks, up to and including the first piece on each line
+fn piece_attacks(board: &Board, pos: (usize, usize), piece_type: PieceType) -> Vec<(usize, usize)> {
+    let from = (pos.0 as isize, pos.1 as isize);
+    if piece_type == PieceType::Knight {
+        return KNIGHT_VECS.into_iter().filter_map(|vec| on_board((from.0 + vec.0, from.1 + vec.1))).collect();
+    }
+    let vecs: Vec<(isize, isize)> = match piece_type {
+        PieceType::Bishop => BISHOP_VECS.to_vec(),
+        PieceType::Rook => ROOK_VECS.to_vec(),
+        PieceType::Queen => ROOK_VECS.into_iter().chain(BISHOP_VECS).collect(),
+        _ => vec![],
+    };
+    let mut attacks = vec![];
+    for vec in vecs {
+        let mut current = (from.0 + vec.0, from.1 + vec.1);
+        while let Some(target) = on_board(current) {
+            attacks.push(target);
+            if board[target.0][target.1].is_some() {
+                break;
+            }
+            current = (current.0 + vec.0, current.1 + vec.1);
+        }
+    }
+    attacks
+}
+
+// Pawn ranks of each file, for one color
+fn pawn_files(board: &Board, color: Color) -> [Vec<usize>; 8] {
+    let mut files: [Vec<usize>; 8] = Default::default();
+    for (file, ranks) in files.iter_mut().enumerate() {
+        ranks.extend((0..8).filter(|&rank| is_piece(board, (file, rank), PieceType::Pawn, color)));
+    }
+    files
+}
+
+fn neighbour_files(file: usize) -> impl Iterator<Item = usize> {
+    file.saturating_sub(1)..=(file + 1).min(7)
+}
+
+fn pawn_structure(board: &Board, color: Color, own: &[Vec<usize>; 8], enemy: &[Vec<usize>; 8], params: &EvalParams) -> Score {
+    let mut score = Score::default();
+    for (file, ranks) in own.iter().enumerate() {
+        if ranks.len() > 1 {
+            score += params.doubled_pawn * (ranks.len() as i32 - 1);
+        }
+        let adjacent: Vec<usize> = neighbour_files(file).filter(|&other| other != file).flat_map(|other| own[other].iter().map(|&rank| relative_rank(color, rank))).collect();
+        for &rank in ranks {
+            let relative = relative_rank(color, rank);
+            let passed = neighbour_files(file).all(|other| enemy[other].iter().all(|&enemy_rank| relative_rank(color, enemy_rank) <= relative));
+            if passed {
+                score += params.passed_pawn[relative];
+            }
+            if adjacent.is_empty() {
+                score += params.isolated_pawn;
+                continue;
+            }
+            // No pawn beside or behind can come to support it, and it can't safely advance
+            let stop = (file, (rank as isize + forward(color)) as usize);
+            if adjacent.iter().all(|&other| other > relative) && stop.1 < 8 && attacked_by_pawn(board, stop, color.opponent()) {
+                score += params.backward_pawn;
+            }
+        }
+    }
+    score
+}
+
+fn king_safety(board: &Board, color: Color, own: &[Vec<usize>; 8], params: &EvalParams) -> Score {
+    let king = match crate::find_king(board, color) {
+        Some(king) => king,
+        None => return Score::default(),
+    };
+    let mut score = Score::default();
+
+    // Pawns on the two ranks in front of a king that stayed home
+    if relative_rank(color, king.1) <= 1 {
+        for file in neighbour_files(king.0) {
+            let shield = (1..=2).filter_map(|distance| on_board((file as isize, king.1 as isize + distance * forward(color))))
+                .filter(|&pos| is_piece(board, pos, PieceType::Pawn, color))
+                .count();
+            score += params.pawn_shield * shield as i32;
+            if own[file].is_empty() {
+                score += params.king_open_file;
+            }
+        }
+    }
+
+    let near_king = |pos: (usize, usize)| pos.0.abs_diff(king.0) <= 1 && pos.1.abs_diff(king.1) <= 1;
+    for (file, squares) in board.iter().enumerate() {
+        for (rank, square) in squares.iter().enumerate() {
+            let piece = match square {
+                Some(piece) if piece.color != color && !matches!(piece.piece_type, PieceType::Pawn | PieceType::King) => piece,
+                _ => continue,
+            };
+            let attacks = piece_attacks(board, (file, rank), piece.piece_type).into_iter().filter(|&pos| near_king(pos)).count();
+            score += params.king_attack[piece_index(piece.piece_type) - 1] * attacks as i32;
+        }
+    }
+    score
+}
+
+// Everything but the king safety and pawn structure, piece by piece
+fn pieces(board: &Board, color: Color, own: &[Vec<usize>; 8], enemy: &[Vec<usize>; 8], params: &EvalParams, trace: &mut EvalTrace) {
+    let mut bishops = 0;
+    for (file, squares) in board.iter().enumerate() {
+        for (rank, square) in squares.iter().enumerate() {
+            let piece = match square {
+                Some(piece) if piece.color == color => piece,
+                _ => continue,
+            };
+            let index = piece_index(piece.piece_type);
+            trace.add(Term::Material, color, params.material[index]);
+            trace.add(Term::PieceSquares, color, s(square_bonus(&params.pst_mg[index], color, (file, rank)), square_bonus(&params.pst_eg[index], color, (file, rank))));
+
+            if matches!(piece.piece_type, PieceType::Pawn | PieceType::King) {
+                continue;
+            }
+            let mobility = piece_attacks(board, (file, rank), piece.piece_type).into_iter()
+                .filter(|&pos| !matches!(board[pos.0][pos.1], Some(other) if other.color == color) && !attacked_by_pawn(board, pos, color.opponent()))
+                .count();
+            trace.add(Term::Mobility, color, params.mobility[index - 1] * mobility as i32);
+
+            match piece.piece_type {
+                PieceType::Bishop => bishops += 1,
+                PieceType::Rook if own[file].is_empty() => {
+                    trace.add(Term::Rooks, color, if enemy[file].is_empty() { params.rook_open_file } else { params.rook_semi_open_file });
+                },
+                _ => (),
+            }
+        }
+    }
+    if bishops >= 2 {
+        trace.add(Term::BishopPair, color, params.bishop_pair);
+    }
+}
+
+fn game_phase(board: &Board, params: &EvalParams) -> i32 {
+    let phase: i32 = board.iter().flatten().flatten().map(|piece| params.phase[piece_index(piece.piece_type)]).sum();
+    phase.min(MAX_PHASE)
+}
+
+// In centipawns from the side to move's point of view
+pub fn evaluate(position: &Position) -> i32 {
+    evaluate_with(position, &DEFAULT_PARAMS)
+}
+
+pub fn evaluate_with(position: &Position, params: &EvalParams) -> i32 {
+    let score = trace_with(position, params).score();
+    match position.side_to_move {
+        Color::White => score,
+        Color::Black => -score,
+    }
+}
+
+// The evaluation term by term, to see why a position is scored the way it is
+pub fn trace(position: &Position) -> EvalTrace {
+    trace_with(position, &DEFAULT_PARAMS)
+}
+
+pub fn trace_with(position: &Position, params: &EvalParams) -> EvalTrace {
+    let board = &position.board;
+    let mut trace = EvalTrace { phase: game_phase(board, params), ..EvalTrace::default() };
+    for color in [Color::White, Color::Black] {
+        let own = pawn_files(board, color);
+        let enemy = pawn_files(board, color.opponent());
+        pieces(board, color, &own, &enemy, params, &mut trace);
+        trace.add(Term::Pawns, color, pawn_structure(board, color, &own, &enemy, params));
+        trace.add(Term::KingSafety, color, king_safety(board, color, &own, params));
+    }
+    trace
+}
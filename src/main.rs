@@ -1,4 +1,33 @@
-use std::{io::{stdin, stdout, Write}, fmt::Debug};
+use std::{io::{stdin, stdout, Write}, fmt::Debug, fs::File, sync::Arc, thread, time::Duration};
+
+use pgn::{Game, MoveNode, PgnReader};
+use polyglot::{polyglot_key, Book, BookSelection};
+use position::{Move, Position};
+use search::{Limits, SearchHandle, SearchOptions, SearchResult, SearchStats, Searcher};
+use skill::Skill;
+use tablebase::Tablebase;
+use uciclient::UciEngine;
+
+pub mod eco;
+pub mod epd;
+pub mod eval;
+pub mod matchplay;
+pub mod movepick;
+pub mod outcome;
+pub mod pgn;
+pub mod polyglot;
+pub mod position;
+pub mod problem;
+pub mod rng;
+pub mod search;
+pub mod see;
+pub mod skill;
+pub mod tablebase;
+pub mod timeman;
+pub mod tt;
+pub mod uci;
+pub mod uciclient;
+pub mod xboard;
 
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Color {
@@ -6,6 +35,15 @@ pub enum Color {
     Black,
 }
 
+impl Color {
+    pub fn opponent(self) -> Self {
+        match self {
+            Color::White => Color::Black,
+            Color::Black => Color::White,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum PieceType {
     Pawn,
@@ -16,6 +54,18 @@ pub enum PieceType {
     King,
 }
 
+// Indexed by [file][rank], A1 is board[0][0]
+pub type Board = [[Option<Piece>; 8]; 8];
+
+pub const KNIGHT_VECS: [(isize, isize); 8] = [(1, 2), (2, 1), (2, -1), (1, -2), (-1, -2), (-2, -1), (-2, 1), (-1, 2)];
+pub const KING_VECS: [(isize, isize); 8] = [(0, 1), (1, 1), (1, 0), (1, -1), (0, -1), (-1, -1), (-1, 0), (-1, 1)];
+pub const ROOK_VECS: [(isize, isize); 4] = [(0, 1), (1, 0), (0, -1), (-1, 0)];
+pub const BISHOP_VECS: [(isize, isize); 4] = [(1, 1), (1, -1), (-1, -1), (-1, 1)];
+
+// Return true if moving the piece from start_pos to end_pos is legal
+pub type CheckMove = fn(&Board, (usize, usize), (usize, usize)) -> bool;
+pub type ExecMove = fn(&mut Board, (usize, usize), (usize, usize));
+
 #[derive(Clone, Copy)]
 pub struct Piece {
     color: Color,
@@ -23,11 +73,22 @@ pub struct Piece {
     castle: Option<bool>,
     jump: Option<bool>,
     en_passant: Option<bool>,
-    check_move: fn(&[[Option<Piece>; 8]; 8], (usize, usize), (usize, usize)) -> bool,
-    exec_move: fn(&mut [[Option<Piece>; 8]; 8], (usize, usize), (usize, usize)),
+    check_move: CheckMove,
+    exec_move: ExecMove,
 }
 
 impl Piece {
+    pub fn new(piece_type: PieceType, color: Color) -> Self {
+        match piece_type {
+            PieceType::Pawn => PAWN,
+            PieceType::Rook => ROOK,
+            PieceType::Knight => KNIGHT,
+            PieceType::Bishop => BISHOP,
+            PieceType::Queen => QUEEN,
+            PieceType::King => KING,
+        }.color(color)
+    }
+
     pub fn color(mut self, color: Color) -> Self {
         self.color = color;
         self
@@ -46,7 +107,7 @@ const PAWN: Piece = Piece {
     castle: None,
     jump: Some(true),
     en_passant: Some(false),
-    check_move: |board: &[[Option<Piece>; 8]; 8], start_pos: (usize, usize), end_pos: (usize, usize)| -> bool {
+    check_move: |board: &Board, start_pos: (usize, usize), end_pos: (usize, usize)| -> bool {
 
         let is_legit;
 
@@ -80,18 +141,8 @@ const PAWN: Piece = Piece {
                             None => {
                                 let en_passant_piece_opt = board[end_pos.0][end_pos.1 - 1];
                                 match en_passant_piece_opt {
-                                    Some(en_passant_piece) => {
-                                        if en_passant_piece.piece_type == PieceType::Pawn {
-                                            if en_passant_piece.en_passant.unwrap() && en_passant_piece.color == Color::Black {
-                                                is_legit = true;
-                                            } else {
-                                                return false;
-                                            }
-                                        } else {
-                                            return false;
-                                        }
-                                    },
-                                    None => return false, // No piece for a en passant capture
+                                    Some(en_passant_piece) if en_passant_piece.piece_type == PieceType::Pawn && en_passant_piece.en_passant.unwrap() && en_passant_piece.color == Color::Black => is_legit = true,
+                                    _ => return false, // No pawn for a en passant capture
                                 }
                             },
                         }
@@ -101,7 +152,7 @@ const PAWN: Piece = Piece {
                             None => is_legit = true,
                         }
                     }
-                } else if move_vec.1 == 2 && piece.jump.unwrap() {
+                } else if move_vec.1 == 2 && move_vec.0 == 0 && piece.jump.unwrap() {
                     match end_opt {
                         Some(_) => return false,
                         None => {
@@ -132,18 +183,8 @@ const PAWN: Piece = Piece {
                             None => {
                                 let en_passant_piece_opt = board[end_pos.0][end_pos.1 + 1];
                                 match en_passant_piece_opt {
-                                    Some(en_passant_piece) => {
-                                        if en_passant_piece.piece_type == PieceType::Pawn {
-                                            if en_passant_piece.en_passant.unwrap() && en_passant_piece.color == Color::White {
-                                                is_legit = true;
-                                            } else {
-                                                return false;
-                                            }
-                                        } else {
-                                            return false;
-                                        }
-                                    },
-                                    None => return false, // No piece for a en passant capture
+                                    Some(en_passant_piece) if en_passant_piece.piece_type == PieceType::Pawn && en_passant_piece.en_passant.unwrap() && en_passant_piece.color == Color::White => is_legit = true,
+                                    _ => return false, // No pawn for a en passant capture
                                 }
                             },
                         }
@@ -153,7 +194,7 @@ const PAWN: Piece = Piece {
                             None => is_legit = true,
                         }
                     }
-                } else if move_vec.1 == -2 && piece.jump.unwrap() {
+                } else if move_vec.1 == -2 && move_vec.0 == 0 && piece.jump.unwrap() {
                     match end_opt {
                         Some(_) => return false,
                         None => {
@@ -170,42 +211,28 @@ const PAWN: Piece = Piece {
         }
 
         if is_legit {
-            let mut king_pos = (0, 0);
-            for i in 0..8 {
-                for j in 0..8 {
-                    match board[i][j] {
-                        Some(piece) => if piece.piece_type == PieceType::King && piece.color == current_color {
-                            king_pos = (i, j);
-                        } else {
-                            continue;
-                        },
-                        None => continue,
-                    }
-                }
-            }
-            dbg!(check_check(board, start_pos, end_pos, king_pos));
-            if !check_check(board, start_pos, end_pos, king_pos) {
-                return true;
-            } else {
-                return false;
-            }
+            return match find_king(board, current_color) {
+                Some(king_pos) => !check_check(board, start_pos, end_pos, king_pos),
+                None => true, // No king to protect
+            };
         }
 
-        return false;
+        false
     },
-    exec_move: |board: &mut [[Option<Piece>; 8]; 8], start_pos: (usize, usize), end_pos: (usize, usize)| {
+    exec_move: |board: &mut Board, start_pos: (usize, usize), end_pos: (usize, usize)| {
         if !(PAWN.check_move)(board, start_pos, end_pos) {
             return;
         }
         let move_vec: (isize, isize) = ((end_pos.0 as isize - start_pos.0 as isize), (end_pos.1 as isize - start_pos.1 as isize));
-        if move_vec.1.abs() == 2 {
-            board[end_pos.0][end_pos.1] = board[start_pos.0][start_pos.1];
-            board[start_pos.0][start_pos.1] = None;
-            board[end_pos.0][end_pos.1].unwrap().jump = Some(false);
-            board[end_pos.0][end_pos.1].unwrap().en_passant = Some(true);
-        } else {
-            board[end_pos.0][end_pos.1] = board[start_pos.0][start_pos.1];
-            board[start_pos.0][start_pos.1] = None;
+        if move_vec.0 != 0 && board[end_pos.0][end_pos.1].is_none() {
+            // En passant capture, the taken pawn is next to the starting square
+            board[end_pos.0][start_pos.1] = None;
+        }
+        board[end_pos.0][end_pos.1] = board[start_pos.0][start_pos.1];
+        board[start_pos.0][start_pos.1] = None;
+        if let Some(piece) = &mut board[end_pos.0][end_pos.1] {
+            piece.jump = Some(false);
+            piece.en_passant = Some(move_vec.1.abs() == 2);
         }
     },
 };
@@ -215,7 +242,7 @@ const ROOK: Piece = Piece {
     castle: Some(true),
     jump: None,
     en_passant: None,
-    check_move: |board: &[[Option<Piece>; 8]; 8], start_pos: (usize, usize), end_pos: (usize, usize)| -> bool {
+    check_move: |board: &Board, start_pos: (usize, usize), end_pos: (usize, usize)| -> bool {
 
         let is_legit;
 
@@ -232,39 +259,10 @@ const ROOK: Piece = Piece {
             return false;
         }
 
-        let mut pos_set: Vec<Option<Piece>> = vec![];
-
-        if move_vec.0 != 0 {
-            if move_vec.0 < 0 {
-                for i in end_pos.0..start_pos.0 {
-                    pos_set.push(board[i][start_pos.1]);
-                }
-            } else if move_vec.0 > 0 {
-                for i in (start_pos.0 + 1)..(end_pos.0 + 1) {
-                    pos_set.push(board[i][start_pos.1]);
-                }
-            }
-        } else if move_vec.1 != 0 {
-            if move_vec.1 < 0 {
-                for i in end_pos.1..start_pos.1 {
-                    pos_set.push(board[start_pos.0][i]);
-                }
-            } else if move_vec.1 > 0 {
-                for i in (start_pos.1 + 1)..(end_pos.1 + 1) {
-                    pos_set.push(board[start_pos.0][i]);
-                }
-            }
-        } else {
+        if !path_is_clear(board, start_pos, end_pos) {
             return false;
         }
 
-        for i in 0..(pos_set.len() - 1) {
-            match pos_set[i] {
-                Some(_) => return false,
-                None => continue,
-            }
-        }
-
         match piece.color {
             Color::White => {
                 match end_opt {
@@ -287,35 +285,23 @@ const ROOK: Piece = Piece {
         }
 
         if is_legit {
-            let mut king_pos = (0, 0);
-            for i in 0..8 {
-                for j in 0..8 {
-                    match board[i][j] {
-                        Some(piece) => if piece.piece_type == PieceType::King && piece.color == current_color {
-                            king_pos = (i, j);
-                        } else {
-                            continue;
-                        },
-                        None => continue,
-                    }
-                }
-            }
-            if !check_check(board, start_pos, end_pos, king_pos) {
-                return true;
-            } else {
-                return false;
-            }
+            return match find_king(board, current_color) {
+                Some(king_pos) => !check_check(board, start_pos, end_pos, king_pos),
+                None => true, // No king to protect
+            };
         }
 
-        return false;
+        false
     },
-    exec_move: |board: &mut [[Option<Piece>; 8]; 8], start_pos: (usize, usize), end_pos: (usize, usize)| {
+    exec_move: |board: &mut Board, start_pos: (usize, usize), end_pos: (usize, usize)| {
         if !(ROOK.check_move)(board, start_pos, end_pos) {
             return;
         }
         board[end_pos.0][end_pos.1] = board[start_pos.0][start_pos.1];
         board[start_pos.0][start_pos.1] = None;
-        board[end_pos.0][end_pos.1].unwrap().castle = Some(false);
+        if let Some(piece) = &mut board[end_pos.0][end_pos.1] {
+            piece.castle = Some(false);
+        }
     },
 };
 const KNIGHT: Piece = Piece {
@@ -324,7 +310,7 @@ const KNIGHT: Piece = Piece {
     castle: None,
     jump: None,
     en_passant: None,
-    check_move: |board: &[[Option<Piece>; 8]; 8], start_pos: (usize, usize), end_pos: (usize, usize)| -> bool {
+    check_move: |board: &Board, start_pos: (usize, usize), end_pos: (usize, usize)| -> bool {
 
         let is_legit;
 
@@ -367,29 +353,15 @@ const KNIGHT: Piece = Piece {
         }
 
         if is_legit {
-            let mut king_pos = (0, 0);
-            for i in 0..8 {
-                for j in 0..8 {
-                    match board[i][j] {
-                        Some(piece) => if piece.piece_type == PieceType::King && piece.color == current_color {
-                            king_pos = (i, j);
-                        } else {
-                            continue;
-                        },
-                        None => continue,
-                    }
-                }
-            }
-            if !check_check(board, start_pos, end_pos, king_pos) {
-                return true;
-            } else {
-                return false;
-            }
+            return match find_king(board, current_color) {
+                Some(king_pos) => !check_check(board, start_pos, end_pos, king_pos),
+                None => true, // No king to protect
+            };
         }
 
-        return false;
+        false
     },
-    exec_move: |board: &mut [[Option<Piece>; 8]; 8], start_pos: (usize, usize), end_pos: (usize, usize)| {
+    exec_move: |board: &mut Board, start_pos: (usize, usize), end_pos: (usize, usize)| {
         if !(KNIGHT.check_move)(board, start_pos, end_pos) {
             return;
         }
@@ -403,7 +375,7 @@ const BISHOP: Piece = Piece {
     castle: None,
     jump: None,
     en_passant: None,
-    check_move: |board: &[[Option<Piece>; 8]; 8], start_pos: (usize, usize), end_pos: (usize, usize)| -> bool {
+    check_move: |board: &Board, start_pos: (usize, usize), end_pos: (usize, usize)| -> bool {
 
         let is_legit;
 
@@ -416,62 +388,18 @@ const BISHOP: Piece = Piece {
         let end_opt = board[end_pos.0][end_pos.1];
 
         if move_vec.0.abs() != move_vec.1.abs() || move_vec.0 == 0 {
-            println!("Error 0");
             return false;
         }
 
-        let mut pos_set: Vec<Option<Piece>> = vec![];
-
-        let mut start_pos_mut = start_pos;
-
-        if move_vec.0 < 0 {
-            if move_vec.1 < 0 {
-                while start_pos_mut.0 != end_pos.0 && start_pos_mut.1 != end_pos.1 {
-                    start_pos_mut.0 -= 1;
-                    start_pos_mut.1 -= 1;
-                    pos_set.push(board[start_pos_mut.0][start_pos_mut.1]);
-                }
-            } else if move_vec.1 > 0 {
-                while start_pos_mut.0 != end_pos.0 && start_pos_mut.1 != end_pos.1 {
-                    start_pos_mut.0 -= 1;
-                    start_pos_mut.1 += 1;
-                    pos_set.push(board[start_pos_mut.0][start_pos_mut.1]);
-                }
-            }
-        } else if move_vec.0 > 0 {
-            if move_vec.1 < 0 {
-                while start_pos_mut.0 != end_pos.0 && start_pos_mut.1 != end_pos.1 {
-                    start_pos_mut.0 += 1;
-                    start_pos_mut.1 -= 1;
-                    pos_set.push(board[start_pos_mut.0][start_pos_mut.1]);
-                }
-            } else if move_vec.1 > 0 {
-                while start_pos_mut.0 != end_pos.0 && start_pos_mut.1 != end_pos.1 {
-                    start_pos_mut.0 += 1;
-                    start_pos_mut.1 += 1;
-                    pos_set.push(board[start_pos_mut.0][start_pos_mut.1]);
-                }
-            }
-        }
-
-        for i in 0..(pos_set.len() - 1) {
-            match pos_set[i] {
-                Some(_) => {
-                    println!("Error 1");
-                    return false;
-                },
-                None => continue,
-            }
+        if !path_is_clear(board, start_pos, end_pos) {
+            return false;
         }
 
         match piece.color {
             Color::White => {
                 match end_opt {
                     Some(end_piece) => match end_piece.color {
-                        Color::White => {
-                            println!("Error 2");
-                            return false;
-                        },
+                        Color::White => return false,
                         Color::Black => is_legit = true,
                     },
                     None => is_legit = true,
@@ -481,10 +409,7 @@ const BISHOP: Piece = Piece {
                 match end_opt {
                     Some(end_piece) => match end_piece.color {
                         Color::White => is_legit = true,
-                        Color::Black => {
-                            println!("Error 3");
-                            return false;
-                        },
+                        Color::Black => return false,
                     },
                     None => is_legit = true,
                 }
@@ -492,30 +417,16 @@ const BISHOP: Piece = Piece {
         }
 
         if is_legit {
-            let mut king_pos = (0, 0);
-            for i in 0..8 {
-                for j in 0..8 {
-                    match board[i][j] {
-                        Some(piece) => if piece.piece_type == PieceType::King && piece.color == current_color {
-                            king_pos = (i, j);
-                        } else {
-                            continue;
-                        },
-                        None => continue,
-                    }
-                }
-            }
-            if !check_check(board, start_pos, end_pos, king_pos) {
-                return true;
-            } else {
-                return false;
-            }
+            return match find_king(board, current_color) {
+                Some(king_pos) => !check_check(board, start_pos, end_pos, king_pos),
+                None => true, // No king to protect
+            };
         }
 
-        return false;
+        false
 
     },
-    exec_move: |board: &mut [[Option<Piece>; 8]; 8], start_pos: (usize, usize), end_pos: (usize, usize)| {
+    exec_move: |board: &mut Board, start_pos: (usize, usize), end_pos: (usize, usize)| {
         if !(BISHOP.check_move)(board, start_pos, end_pos) {
             return;
         }
@@ -529,14 +440,10 @@ const QUEEN: Piece = Piece {
     castle: None,
     jump: None,
     en_passant: None,
-    check_move: |board: &[[Option<Piece>; 8]; 8], start_pos: (usize, usize), end_pos: (usize, usize)| -> bool {
-        if (ROOK.check_move)(board, start_pos, end_pos) || (BISHOP.check_move)(board, start_pos, end_pos) {
-            return true;
-        } else {
-            return false;
-        }
+    check_move: |board: &Board, start_pos: (usize, usize), end_pos: (usize, usize)| -> bool {
+        (ROOK.check_move)(board, start_pos, end_pos) || (BISHOP.check_move)(board, start_pos, end_pos)
     },
-    exec_move: |board: &mut [[Option<Piece>; 8]; 8], start_pos: (usize, usize), end_pos: (usize, usize)| {
+    exec_move: |board: &mut Board, start_pos: (usize, usize), end_pos: (usize, usize)| {
         if !(QUEEN.check_move)(board, start_pos, end_pos) {
             return;
         }
@@ -550,9 +457,7 @@ const KING: Piece = Piece {
     castle: Some(true),
     jump: None,
     en_passant: None,
-    check_move: |board: &[[Option<Piece>; 8]; 8], start_pos: (usize, usize), end_pos: (usize, usize)| -> bool {
-
-        let is_legit;
+    check_move: |board: &Board, start_pos: (usize, usize), end_pos: (usize, usize)| -> bool {
 
         let move_vec: (isize, isize) = ((end_pos.0 as isize - start_pos.0 as isize), (end_pos.1 as isize - start_pos.1 as isize));
         let piece = match board[start_pos.0][start_pos.1] {
@@ -565,157 +470,762 @@ const KING: Piece = Piece {
         };
         let end_opt = board[end_pos.0][end_pos.1];
 
-        if piece.castle.unwrap() {
-            if move_vec.1 == 0 {
-                if move_vec.0 == 2 {
-
-                }
+        if move_vec.1 == 0 && move_vec.0.abs() == 2 {
+            // Castling: neither the king nor the rook may have moved
+            if piece.castle != Some(true) {
+                return false;
             }
+            let rook_pos = (if move_vec.0 > 0 { 7 } else { 0 }, start_pos.1);
+            match board[rook_pos.0][rook_pos.1] {
+                Some(rook) => if rook.piece_type != PieceType::Rook || rook.color != piece.color || rook.castle != Some(true) {
+                    return false;
+                },
+                None => return false,
+            }
+            if !path_is_clear(board, start_pos, rook_pos) {
+                return false;
+            }
+            // The king can't castle out of, through or into check
+            let passed_pos = ((start_pos.0 as isize + move_vec.0 / 2) as usize, start_pos.1);
+            let enemy_color = piece.color.opponent();
+            return !is_attacked(board, start_pos, enemy_color) && !is_attacked(board, passed_pos, enemy_color) && !check_check(board, start_pos, end_pos, end_pos);
         }
 
-        if !(move_vec.0.abs() == 1 || move_vec.1.abs() == 1) || (move_vec.0 == 0 && move_vec.1 == 0) {
+        if move_vec.0.abs() > 1 || move_vec.1.abs() > 1 || (move_vec.0 == 0 && move_vec.1 == 0) {
             return false;
         }
 
-        // HAVE TO CHECK IF MOVING KING RESULT IN MATE !!
-        
-
-        match piece.color {
-            Color::White => match end_opt {
-                Some(end_piece) => match end_piece.color {
-                    Color::White => return false,
-                    Color::Black => is_legit = true,
-                },
-                None => is_legit = true,
-            },
-            Color::Black => match end_opt {
-                Some(end_piece) => match end_piece.color {
-                    Color::White => is_legit = true,
-                    Color::Black => return false,
-                },
-                None => is_legit = true,
-            },
-        }
-
-        if is_legit {
-            if !check_check(board, start_pos, end_pos, end_pos) {
-                return true;
-            } else {
+        if let Some(end_piece) = end_opt {
+            if end_piece.color == piece.color {
                 return false;
             }
         }
 
-        return false;
-
+        !check_check(board, start_pos, end_pos, end_pos)
     },
-    exec_move: |board: &mut [[Option<Piece>; 8]; 8], start_pos: (usize, usize), end_pos: (usize, usize)| {
+    exec_move: |board: &mut Board, start_pos: (usize, usize), end_pos: (usize, usize)| {
         if !(KING.check_move)(board, start_pos, end_pos) {
             return;
         }
+        if end_pos.0.abs_diff(start_pos.0) == 2 {
+            // Castling, the rook jumps over the king
+            let rook_file = if end_pos.0 > start_pos.0 { 7 } else { 0 };
+            let rook_end_file = (start_pos.0 + end_pos.0) / 2;
+            board[rook_end_file][start_pos.1] = board[rook_file][start_pos.1];
+            board[rook_file][start_pos.1] = None;
+            if let Some(rook) = &mut board[rook_end_file][start_pos.1] {
+                rook.castle = Some(false);
+            }
+        }
         board[end_pos.0][end_pos.1] = board[start_pos.0][start_pos.1];
         board[start_pos.0][start_pos.1] = None;
+        if let Some(king) = &mut board[end_pos.0][end_pos.1] {
+            king.castle = Some(false);
+        }
     },
 };
 
 fn main() {
+    if std::env::args().any(|arg| arg == "--uci") {
+        uci::run();
+        return;
+    }
+    if std::env::args().any(|arg| arg == "--xboard") {
+        xboard::run();
+        return;
+    }
+    let args: Vec<String> = std::env::args().collect();
+    if let Some(at) = args.iter().position(|arg| arg == "--match") {
+        if let Err(error) = matchplay::MatchConfig::parse(&args[at + 1..]).and_then(|config| matchplay::run(&config)) {
+            println!("{}", error);
+            println!("{}", matchplay::USAGE);
+        }
+        return;
+    }
 
-    let mut board: [[Option<Piece>; 8]; 8] = [[Some(ROOK.clone().color(Color::White)), Some(PAWN.clone().color(Color::White)), None, None, None, None, Some(PAWN.clone().color(Color::Black)), Some(ROOK.clone().color(Color::Black))],
-                                              [Some(KNIGHT.clone().color(Color::White)), Some(PAWN.clone().color(Color::White)), None, None, None, None, Some(PAWN.clone().color(Color::Black)), Some(KNIGHT.clone().color(Color::Black))],
-                                              [Some(BISHOP.clone().color(Color::White)), Some(PAWN.clone().color(Color::White)), None, None, None, None, Some(PAWN.clone().color(Color::Black)), Some(BISHOP.clone().color(Color::Black))],
-                                              [Some(QUEEN.clone().color(Color::White)), Some(PAWN.clone().color(Color::White)), None, None, None, None, Some(PAWN.clone().color(Color::Black)), Some(QUEEN.clone().color(Color::Black))],
-                                              [Some(KING.clone().color(Color::White)), Some(PAWN.clone().color(Color::White)), None, None, None, None, Some(PAWN.clone().color(Color::Black)), Some(KING.clone().color(Color::Black))],
-                                              [Some(BISHOP.clone().color(Color::White)), Some(PAWN.clone().color(Color::White)), None, None, None, None, Some(PAWN.clone().color(Color::Black)), Some(BISHOP.clone().color(Color::Black))],
-                                              [Some(KNIGHT.clone().color(Color::White)), Some(PAWN.clone().color(Color::White)), None, None, None, None, Some(PAWN.clone().color(Color::Black)), Some(KNIGHT.clone().color(Color::Black))],
-                                              [Some(ROOK.clone().color(Color::White)), Some(PAWN.clone().color(Color::White)), None, None, None, None, Some(PAWN.clone().color(Color::Black)), Some(ROOK.clone().color(Color::Black))]];
-
-    // match board[0][1] {
-    //     Some(piece) => println!("Result : {}", (piece.check_move)(&board, (0, 1), (0, 2))),
-    //     None => println!("Error, no piece selected"),
-    // }
+    // Every position of the game so far, with the SAN of the move leading to it
+    let mut history: Vec<(Position, String)> = vec![(Position::new(), String::new())];
+    let mut current = 0;
+    let mut book: Option<Book<File>> = None;
+    let mut tablebase: Option<Arc<Tablebase>> = None;
+    let mut searcher = Searcher::new();
+    let mut limits = Limits::movetime(Duration::from_secs(1));
+    // The color the engine plays, if any
+    let mut engine_color: Option<Color> = None;
+    // A UCI engine playing instead of this one
+    let mut external: Option<UciEngine> = None;
+    // Thinking on the user's time about the position after the reply it expects
+    let mut ponder = false;
+    // Reply the engine expects, with the key of the position it is played from
+    let mut expected: Option<(u64, Move)> = None;
+    // The ponder search, when the user played the expected move
+    let mut pondered: Option<SearchResult> = None;
 
     loop {
-        display_board(&board);
+        let position = history[current].0;
+        display_board(&position.board);
+        let positions: Vec<Position> = history[..=current].iter().map(|(position, _)| *position).collect();
+        if let Some(opening) = eco::classify(&positions) {
+            println!("{}", opening);
+        }
+        if position.is_checkmate() {
+            println!("Checkmate");
+        } else if position.is_stalemate() {
+            println!("Stalemate");
+        } else if position.in_check() {
+            println!("Check");
+        }
         println!();
+
+        // The engine only plays at the end of the game, not while going through it with prev
+        let game_over = position.legal_moves().is_empty();
+        if engine_color == Some(position.side_to_move) && current + 1 == history.len() && !game_over {
+            let played = match pondered.take() {
+                Some(result) => {
+                    println!("Ponder hit");
+                    print_analysis(&position, &result);
+                    result.best_move.map(|mv| (mv, result.pv.get(1).copied()))
+                },
+                None => match &mut external {
+                    Some(external) => external_move(&history, external, limits.clone()),
+                    None => engine_move(&history, &mut book, &tablebase, &mut searcher, limits.clone()),
+                },
+            };
+            if let Some((mv, reply)) = played {
+                let san = position.san(mv);
+                println!("Engine plays {}", move_label(&position, &san));
+                let mut next = position;
+                next.make_move(mv);
+                expected = reply.map(|reply| (polyglot_key(&next), reply));
+                history.push((next, san));
+                current += 1;
+                continue
+            }
+        }
+
         let mut move_expr = String::new();
         print!("-> ");
         match stdout().flush() {
             Ok(_) => (),
             Err(_) => panic!(),
         };
-        stdin().read_line(&mut move_expr).expect("Error while reading stdin");
+        let engine_to_reply = engine_color == Some(position.side_to_move.opponent()) && current + 1 == history.len();
+        let ponder_move = expected
+            .filter(|&(key, mv)| ponder && external.is_none() && engine_to_reply && key == polyglot_key(&position) && position.is_legal(mv))
+            .map(|(_, mv)| mv);
+        let read = match ponder_move {
+            Some(ponder_move) => {
+                let mut after = position;
+                after.make_move(ponder_move);
+                let positions: Vec<Position> = history[..=current].iter().map(|(position, _)| *position).collect();
+                searcher.set_game_history(&positions);
+                let signals = searcher.signals();
+                let ponder_limits = Limits { ponder: true, ..limits.clone() };
+                thread::scope(|scope| {
+                    let search = scope.spawn(|| searcher.search(&after, ponder_limits));
+                    let read = stdin().read_line(&mut move_expr);
+                    // The search goes on when the user played the expected move, and is thrown away otherwise
+                    let hit = parse_input_move(&position, move_expr.trim_end()) == Some(ponder_move);
+                    if hit {
+                        signals.ponderhit();
+                    } else {
+                        signals.stop();
+                    }
+                    if let Ok(result) = search.join() {
+                        pondered = Some(result).filter(|_| hit);
+                    }
+                    read
+                })
+            },
+            None => stdin().read_line(&mut move_expr),
+        };
+        if read.expect("Error while reading stdin") == 0 {
+            break;
+        }
         if let Some('\n') = move_expr.chars().next_back() {
             move_expr.pop();
         }
         if let Some('\r') = move_expr.chars().next_back() {
             move_expr.pop();
         }
-        let (start_pos, end_pos) = match parse_move(&move_expr) {
-            Some(t) => t,
-            None => continue,
-        };
-        match board[start_pos.0][start_pos.1] {
-            Some(piece) => match piece.piece_type {
-                PieceType::Pawn => {
-                    if (PAWN.check_move)(&board, start_pos, end_pos) {
-                        (PAWN.exec_move)(&mut board, start_pos, end_pos);
-                    } else {
-                        println!("Enter a valid move");
+
+        let words: Vec<&str> = move_expr.split_whitespace().collect();
+        match words.first() {
+            Some(&"pgn") => {
+                let path = match words.get(1) {
+                    Some(path) => path,
+                    None => {
+                        println!("Usage: pgn <file> [game number]");
                         continue
-                    }
-                },
-                PieceType::Rook => {
-                    if (ROOK.check_move)(&board, start_pos, end_pos) {
-                        (ROOK.exec_move)(&mut board, start_pos, end_pos);
-                    } else {
-                        println!("Enter a valid move");
+                    },
+                };
+                let index = match words.get(2).map(|n| n.parse::<usize>()) {
+                    Some(Ok(n)) if n > 0 => n - 1,
+                    Some(_) => {
+                        println!("Invalid game number");
                         continue
-                    }
-                },
-                PieceType::Knight => {
-                    if (KNIGHT.check_move)(&board, start_pos, end_pos) {
-                        (KNIGHT.exec_move)(&mut board, start_pos, end_pos);
-                    } else {
-                        println!("Enter a valid move");
+                    },
+                    None => 0,
+                };
+                let file = match File::open(path) {
+                    Ok(file) => file,
+                    Err(e) => {
+                        println!("Can't read {}: {}", path, e);
                         continue
-                    }
-                },
-                PieceType::Bishop => {
-                    if (BISHOP.check_move)(&board, start_pos, end_pos) {
-                        (BISHOP.exec_move)(&mut board, start_pos, end_pos);
-                    } else {
-                        println!("Enter a valid move");
+                    },
+                };
+                // Earlier games are skipped without replaying their moves
+                let mut reader = PgnReader::new(file).headers_only(true);
+                let skipped = reader.by_ref().take(index).count();
+                let game = match reader.headers_only(false).next() {
+                    Some(Ok(game)) => game,
+                    Some(Err(e)) => {
+                        println!("{}: {}", path, e);
+                        continue
+                    },
+                    None => {
+                        println!("{} only has {} games", path, skipped);
+                        continue
+                    },
+                };
+                println!("{} - {} ({}), {} moves, use next and prev to replay", game.tag("White").unwrap_or("?"), game.tag("Black").unwrap_or("?"), game.tag("Result").unwrap_or("*"), game.moves.len());
+                history = game.positions().into_iter().zip(std::iter::once(String::new()).chain(game.moves.into_iter().map(|node| node.san))).collect();
+                current = 0;
+                continue
+            },
+            Some(&"games") => {
+                let path = match words.get(1) {
+                    Some(path) => path,
+                    None => {
+                        println!("Usage: games <file>");
+                        continue
+                    },
+                };
+                let file = match File::open(path) {
+                    Ok(file) => file,
+                    Err(e) => {
+                        println!("Can't read {}: {}", path, e);
                         continue
+                    },
+                };
+                for (i, game) in PgnReader::new(file).headers_only(true).enumerate() {
+                    match game {
+                        Ok(game) => println!("{}. {} - {}, {} {}, {}", i + 1, game.tag("White").unwrap_or("?"), game.tag("Black").unwrap_or("?"), game.tag("Event").unwrap_or("?"), game.tag("Date").unwrap_or("?"), game.tag("Result").unwrap_or("*")),
+                        Err(e) => println!("{}. {}", i + 1, e),
                     }
-                },
-                PieceType::Queen => {
-                    if (QUEEN.check_move)(&board, start_pos, end_pos) {
-                        (QUEEN.exec_move)(&mut board, start_pos, end_pos);
-                    } else {
-                        println!("Enter a valid move");
+                }
+                continue
+            },
+            Some(&"epd") => {
+                let path = match words.get(1) {
+                    Some(path) => path,
+                    None => {
+                        println!("Usage: epd <file> [depth <n>|time <seconds>]");
                         continue
+                    },
+                };
+                let text = match std::fs::read_to_string(path) {
+                    Ok(text) => text,
+                    Err(e) => {
+                        println!("Can't read {}: {}", path, e);
+                        continue
+                    },
+                };
+                // With a depth or a time per position, run the positions as a test suite
+                if let Some(&kind) = words.get(2) {
+                    let value = words.get(3).copied().unwrap_or("");
+                    let suite_limits = match kind {
+                        "depth" => match value.parse::<u32>() {
+                            Ok(depth) if depth > 0 => Limits::depth(depth),
+                            _ => {
+                                println!("Invalid depth");
+                                continue
+                            },
+                        },
+                        "time" => match value.parse::<f64>() {
+                            Ok(seconds) if seconds > 0.0 && seconds.is_finite() => Limits::movetime(Duration::from_secs_f64(seconds)),
+                            _ => {
+                                println!("Invalid time");
+                                continue
+                            },
+                        },
+                        _ => {
+                            println!("Usage: epd <file> [depth <n>|time <seconds>]");
+                            continue
+                        },
+                    };
+                    let records: Vec<epd::EpdRecord> = epd::parse_epd(&text).into_iter().filter_map(|record| record.map_err(|e| println!("{}: {}", path, e)).ok()).collect();
+                    let mut nodes = 0;
+                    let mut stats = SearchStats::default();
+                    let report = epd::run_suite(&records, |position| {
+                        let result = searcher.search(position, suite_limits.clone());
+                        nodes += result.nodes;
+                        stats.add(&result.stats);
+                        result.best_move
+                    }, |result| println!("{}", result));
+                    println!("{}", report);
+                    println!("{} nodes, {} in quiescence, {:.1}% of the cutoffs by the first move", nodes, stats.quiescence_nodes, 100.0 * stats.first_move_rate());
+                    continue
+                }
+                for (i, record) in epd::parse_epd(&text).into_iter().enumerate() {
+                    match record {
+                        Ok(record) => {
+                            let sans = |moves: Vec<Move>| moves.into_iter().map(|mv| record.position.san(mv)).collect::<Vec<String>>().join(" ");
+                            println!("{}: bm {} am {}", record.id().unwrap_or(&format!("#{}", i + 1)), sans(record.best_moves()), sans(record.avoid_moves()));
+                        },
+                        Err(e) => println!("{}: {}", path, e),
                     }
-                },
-                PieceType::King => {
-                    if (KING.check_move)(&board, start_pos, end_pos) {
-                        (KING.exec_move)(&mut board, start_pos, end_pos);
-                    } else {
-                        println!("Enter a valid move");
+                }
+                continue
+            },
+            Some(&"book") => {
+                match words.get(1) {
+                    Some(path) => match Book::open(path) {
+                        Ok(opened) => {
+                            book = Some(opened);
+                            println!("Using {} as opening book", path);
+                        },
+                        Err(e) => println!("Can't read {}: {}", path, e),
+                    },
+                    None => println!("Usage: book <file>"),
+                }
+                continue
+            },
+            Some(&"hint") => {
+                let moves = match &mut book {
+                    Some(book) => book.moves(&position).unwrap_or_default(),
+                    None => vec![],
+                };
+                if moves.is_empty() {
+                    searcher.set_game_history(&positions[..current]);
+                    let result = searcher.search(&position, limits.clone());
+                    match result.best_move {
+                        Some(_) if result.lines.len() > 1 => print_analysis(&position, &result),
+                        Some(mv) => println!("{} ({}, depth {}, {})", position.san(mv), format_score(result.score), result.depth, line_san(&position, &result.pv)),
+                        None => println!("No legal move"),
+                    }
+                    continue
+                }
+                let total: u32 = moves.iter().map(|&(_, weight)| weight as u32).sum();
+                for (mv, weight) in moves {
+                    println!("{} ({}%)", position.san(mv), weight as u32 * 100 / total.max(1));
+                }
+                continue
+            },
+            Some(&"analyze") => {
+                if game_over {
+                    println!("No legal move");
+                    continue
+                }
+                // Searches until enter is pressed, printing each iteration
+                searcher.set_game_history(&positions[..current]);
+                let handle = SearchHandle::start(searcher, position, Limits::default(), move |info| {
+                    println!("Depth {}, score {}, {} nodes, {} nps: {}", info.depth, format_score(info.score), info.nodes, info.nps, line_san(&position, &info.pv));
+                });
+                let mut line = String::new();
+                let _ = stdin().read_line(&mut line);
+                handle.stop();
+                let (back, result) = handle.join();
+                searcher = back;
+                if let Some(mv) = result.best_move {
+                    println!("Best move {}", position.san(mv));
+                }
+                continue
+            },
+            Some(&"tb") => {
+                if let Some(path) = words.get(1) {
+                    match Tablebase::open(path) {
+                        Ok(opened) => {
+                            println!("Using tablebases from {} (up to {} pieces)", path, opened.max_pieces());
+                            let opened = Arc::new(opened);
+                            searcher.set_tablebase(Some(opened.clone()));
+                            tablebase = Some(opened);
+                        },
+                        Err(e) => println!("Can't read {}: {}", path, e),
+                    }
+                    continue
+                }
+                let tablebase = match &tablebase {
+                    Some(tablebase) => tablebase,
+                    None => {
+                        println!("Usage: tb <directory>, then tb to probe the position");
                         continue
+                    },
+                };
+                match (tablebase.probe_wdl(&position), tablebase.probe_dtz(&position)) {
+                    (Some(wdl), Some(dtz)) => println!("{:?}, DTZ {}", wdl, dtz),
+                    _ => {
+                        println!("Position not in the tablebases");
+                        continue
+                    },
+                }
+                if let Some(root_moves) = tablebase.probe_root(&position) {
+                    for root_move in root_moves {
+                        println!("{} {:?} (DTZ {})", position.san(root_move.mv), root_move.wdl, root_move.dtz);
                     }
-                },
+                }
+                continue
+            },
+            Some(&"save") => {
+                let path = match words.get(1) {
+                    Some(path) => path,
+                    None => {
+                        println!("Usage: save <file>");
+                        continue
+                    },
+                };
+                let game = history_game(&history[..=current]);
+                match std::fs::write(path, game.to_pgn()) {
+                    Ok(()) => println!("Saved the game to {}", path),
+                    Err(e) => println!("Can't write {}: {}", path, e),
+                }
+                continue
+            },
+            Some(&"play") => {
+                engine_color = match words.get(1) {
+                    Some(&"white") => Some(Color::Black),
+                    Some(&"black") => Some(Color::White),
+                    Some(&"none") => None,
+                    _ => {
+                        println!("Usage: play white|black|none");
+                        continue
+                    },
+                };
+                continue
+            },
+            Some(&"engine") => {
+                match words.get(1) {
+                    Some(&"off") => {
+                        if let Some(engine) = external.take() {
+                            engine.quit();
+                        }
+                    },
+                    Some(program) => match UciEngine::spawn(program, &words[2..]) {
+                        Ok(engine) => {
+                            println!("Loaded {}", engine.name);
+                            if let Some(previous) = external.replace(engine) {
+                                previous.quit();
+                            }
+                        },
+                        Err(error) => println!("Cannot start {}: {}", program, error),
+                    },
+                    None => match &external {
+                        Some(engine) => println!("{} by {}", engine.name, engine.author),
+                        None => println!("Usage: engine <program> [arguments]|off"),
+                    },
+                }
+                continue
+            },
+            Some(&"ponder") => {
+                match words.get(1) {
+                    Some(&"on") => ponder = true,
+                    Some(&"off") => ponder = false,
+                    _ => println!("Usage: ponder on|off"),
+                }
+                continue
+            },
+            Some(&"go") => {
+                if game_over {
+                    println!("No legal move");
+                    continue
+                }
+                // Take over the side to move
+                engine_color = Some(position.side_to_move);
+                history.truncate(current + 1);
+                continue
+            },
+            Some(&"depth") => {
+                match words.get(1).map(|depth| depth.parse::<u32>()) {
+                    Some(Ok(depth)) if depth > 0 => limits = Limits::depth(depth),
+                    _ => println!("Usage: depth <plies>"),
+                }
+                continue
+            },
+            Some(&"eval") => {
+                println!("{}", eval::trace(&history[current].0));
+                continue
+            },
+            Some(&"option") => {
+                let mut options = searcher.options();
+                match (words.get(1), words.get(2)) {
+                    (None, _) => {
+                        for name in SearchOptions::NAMES {
+                            println!("{} {}", name, if options.get(name) == Some(true) { "on" } else { "off" });
+                        }
+                    },
+                    (Some(name), Some(&"on")) | (Some(name), Some(&"off")) if options.set(name, words[2] == "on") => searcher.set_options(options),
+                    _ => println!("Usage: option [<name> on|off]"),
+                }
+                continue
+            },
+            Some(&"mate") => {
+                let moves = match words.get(1).map(|moves| moves.parse::<u32>()) {
+                    Some(Ok(moves)) if moves > 0 => moves,
+                    _ => {
+                        println!("Usage: mate <moves>");
+                        continue
+                    },
+                };
+                let keys = problem::solve_mate(&position, moves);
+                if keys.is_empty() {
+                    println!("No mate in {}", moves);
+                }
+                for key in keys {
+                    println!("{}", position.san(key));
+                }
+                continue
+            },
+            Some(&"solve") => {
+                match words.get(1).and_then(|stipulation| problem::Stipulation::parse(stipulation)) {
+                    Some((stipulation, moves)) => println!("{}", problem::solve(&position, stipulation, moves)),
+                    None => println!("Usage: solve #n|h#n|s#n|r#n"),
+                }
+                continue
+            },
+            Some(&"difficulty") => {
+                let skill = match (words.get(1), words.get(2).map(|elo| elo.parse::<u32>())) {
+                    (None, _) => {
+                        let skill = searcher.skill();
+                        println!("Level {} of {} (about {} Elo{})", skill.level(), skill::MAX_LEVEL, skill.elo(), if skill.is_full() { " or more" } else { "" });
+                        continue
+                    },
+                    (Some(&"elo"), Some(Ok(elo))) => Skill::from_elo(elo),
+                    (Some(level), None) => match level.parse::<u32>() {
+                        Ok(level) if level <= skill::MAX_LEVEL => Skill::new(level),
+                        _ => {
+                            println!("Usage: difficulty [0-{}|elo <rating>]", skill::MAX_LEVEL);
+                            continue
+                        },
+                    },
+                    _ => {
+                        println!("Usage: difficulty [0-{}|elo <rating>]", skill::MAX_LEVEL);
+                        continue
+                    },
+                };
+                searcher.set_skill(skill);
+                continue
+            },
+            Some(&"multipv") => {
+                match words.get(1).map(|lines| lines.parse::<usize>()) {
+                    Some(Ok(lines)) if lines > 0 => searcher.set_multi_pv(lines),
+                    _ => println!("Usage: multipv <lines>"),
+                }
+                continue
+            },
+            Some(&"threads") => {
+                match words.get(1).map(|threads| threads.parse::<usize>()) {
+                    Some(Ok(threads)) if threads > 0 => searcher.set_threads(threads),
+                    _ => println!("Usage: threads <count>"),
+                }
+                continue
+            },
+            Some(&"hash") => {
+                match words.get(1).map(|megabytes| megabytes.parse::<usize>()) {
+                    Some(Ok(megabytes)) if megabytes > 0 => searcher.set_hash_size(megabytes),
+                    _ => println!("Usage: hash <megabytes>"),
+                }
+                continue
+            },
+            Some(&"fen") => {
+                match Position::from_fen(&words[1..].join(" ")) {
+                    Ok(position) => {
+                        history = vec![(position, String::new())];
+                        current = 0;
+                    },
+                    Err(e) => println!("{}", e),
+                }
+                continue
+            },
+            Some(&"new") => {
+                history = vec![(Position::new(), String::new())];
+                current = 0;
+                searcher.clear_hash();
+                continue
+            },
+            Some(&"time") => {
+                match words.get(1).map(|seconds| seconds.parse::<f64>()) {
+                    Some(Ok(seconds)) if seconds > 0.0 && seconds.is_finite() => limits = Limits::movetime(Duration::from_secs_f64(seconds)),
+                    _ => println!("Usage: time <seconds per move>"),
+                }
+                continue
+            },
+            Some(&"next") => {
+                if current + 1 < history.len() {
+                    println!("{}", move_label(&history[current].0, &history[current + 1].1));
+                    current += 1;
+                } else {
+                    println!("End of the game");
+                }
+                continue
+            },
+            Some(&"prev") => {
+                if current > 0 {
+                    current -= 1;
+                } else {
+                    println!("Start of the game");
+                }
+                continue
+            },
+            _ => (),
+        }
+
+        let mv = match parse_input_move(&position, &move_expr) {
+            Some(mv) => mv,
+            None => continue,
+        };
+        match position.piece_at(mv.start_pos) {
+            Some(_) => if !position.is_legal(mv) {
+                println!("Enter a valid move");
+                continue
             },
             None => {
                 println!("You have to move a piece");
                 continue
             },
         }
+        let san = position.san(mv);
+        let mut next = position;
+        next.make_move(mv);
+        history.truncate(current + 1);
+        history.push((next, san));
+        current += 1;
     }
 
 }
 
-pub fn mut_pieces(board: &mut [[Option<Piece>; 8]; 8], pos1: (usize, usize), pos2: (usize, usize)) -> (&mut Option<Piece>, &mut Option<Piece>) {
-    
-    let pieces: (&mut Option<Piece>, &mut Option<Piece>);
+// A move typed by the user, as two squares or in SAN, pawns reaching the last rank become queens
+pub fn parse_input_move(position: &Position, expr: &str) -> Option<Move> {
+    match parse_move(expr) {
+        Some((start_pos, end_pos)) => {
+            let mut mv = Move::new(start_pos, end_pos);
+            if let Some(piece) = position.piece_at(start_pos) {
+                if piece.piece_type == PieceType::Pawn && (end_pos.1 == 0 || end_pos.1 == 7) {
+                    mv.promotion = Some(PieceType::Queen);
+                }
+            }
+            Some(mv)
+        },
+        None => position.parse_san(expr),
+    }
+}
+
+// The engine's move: from the book, then the tablebases, then a search. Comes with
+// the reply the search expects, to ponder on.
+pub fn engine_move(history: &[(Position, String)], book: &mut Option<Book<File>>, tablebase: &Option<Arc<Tablebase>>, searcher: &mut Searcher, limits: Limits) -> Option<(Move, Option<Move>)> {
+    let position = history[history.len() - 1].0;
+    if let Some(book) = book {
+        if let Ok(Some(mv)) = book.choose_move(&position, BookSelection::Weighted) {
+            println!("Book move");
+            return Some((mv, None));
+        }
+    }
+    if let Some(root_move) = tablebase.as_ref().and_then(|tablebase| tablebase.probe_root(&position)).and_then(|root_moves| root_moves.first().copied()) {
+        println!("Tablebase: {:?}, DTZ {}", root_move.wdl, root_move.dtz);
+        return Some((root_move.mv, None));
+    }
+    let positions: Vec<Position> = history[..history.len() - 1].iter().map(|(position, _)| *position).collect();
+    searcher.set_game_history(&positions);
+    let result = searcher.search(&position, limits);
+    print_analysis(&position, &result);
+    result.best_move.map(|mv| (mv, result.pv.get(1).copied()))
+}
+
+// The move of the external engine, with the reply it expects
+pub fn external_move(history: &[(Position, String)], engine: &mut UciEngine, limits: Limits) -> Option<(Move, Option<Move>)> {
+    let mut moves = vec![];
+    for pair in history.windows(2) {
+        moves.extend(pair[0].0.parse_san(&pair[1].1));
+    }
+    let position = history[history.len() - 1].0;
+    let best = engine.set_position(&history[0].0, &moves).and_then(|_| engine.search(&limits));
+    match best {
+        Ok(best) => {
+            let info = &best.info;
+            println!("{}: depth {}, score {}: {}", engine.name, info.depth.unwrap_or(0), info.score.map_or("?".to_string(), format_score), line_san(&position, &info.pv));
+            best.best_move.map(|mv| (mv, best.ponder))
+        },
+        Err(error) => {
+            println!("{}: {}", engine.name, error);
+            None
+        },
+    }
+}
+
+// Depth, score and principal variation, a numbered line for each move with MultiPV
+pub fn print_analysis(position: &Position, result: &SearchResult) {
+    if result.lines.len() <= 1 {
+        println!("Depth {}, score {}, {} nodes: {}", result.depth, format_score(result.score), result.nodes, line_san(position, &result.pv));
+        return;
+    }
+    println!("Depth {}, {} nodes", result.depth, result.nodes);
+    for (i, line) in result.lines.iter().enumerate() {
+        println!("{}. {} {}", i + 1, format_score(line.score), line_san(position, &line.pv));
+    }
+}
+
+// "+0.35", or "#3" when mating in 3
+pub fn format_score(score: i32) -> String {
+    match search::mate_in(score) {
+        Some(moves) => format!("#{}", moves),
+        None => format!("{:+.2}", score as f64 / 100.0),
+    }
+}
+
+// Moves played one after the other from position, in SAN
+pub fn line_san(position: &Position, moves: &[Move]) -> String {
+    let mut position = *position;
+    let mut sans = vec![];
+    for &mv in moves {
+        sans.push(position.san(mv));
+        position.make_move(mv);
+    }
+    sans.join(" ")
+}
+
+// The game played in the REPL so far, with its opening in the tags
+pub fn history_game(history: &[(Position, String)]) -> Game {
+    let start = history[0].0;
+    let mut game = Game::default();
+    for (name, value) in [("Event", "?"), ("Site", "?"), ("Date", "????.??.??"), ("Round", "-"), ("White", "?"), ("Black", "?"), ("Result", "*")] {
+        game.set_tag(name, value);
+    }
+    let fen = start.to_fen();
+    if fen != position::START_FEN {
+        game.set_tag("SetUp", "1");
+        game.set_tag("FEN", &fen);
+    }
+    for pair in history.windows(2) {
+        // The SAN in history was produced by the position it is played from
+        if let Some(mv) = pair[0].0.parse_san(&pair[1].1) {
+            game.moves.push(MoveNode { mv, san: pair[1].1.clone(), nags: vec![], starting_comment: None, comments: vec![], variations: vec![] });
+        }
+    }
+    let last = history[history.len() - 1].0;
+    let result = if last.is_checkmate() {
+        match last.side_to_move {
+            Color::White => "0-1",
+            Color::Black => "1-0",
+        }
+    } else if last.is_stalemate() {
+        "1/2-1/2"
+    } else {
+        "*"
+    };
+    game.set_tag("Result", result);
+    game.result = Some(result.to_string());
+    game.classify_opening();
+    game
+}
+
+// "12. Nf3" or "12... Nf6" for a move played from position
+pub fn move_label(position: &Position, san: &str) -> String {
+    match position.side_to_move {
+        Color::White => format!("{}. {}", position.fullmove_number, san),
+        Color::Black => format!("{}... {}", position.fullmove_number, san),
+    }
+}
+
+pub fn mut_pieces(board: &mut Board, pos1: (usize, usize), pos2: (usize, usize)) -> (&mut Option<Piece>, &mut Option<Piece>) {
     
     let mid = if pos1.0 < pos2.0 {
         pos1.0 + 1
@@ -725,7 +1235,7 @@ pub fn mut_pieces(board: &mut [[Option<Piece>; 8]; 8], pos1: (usize, usize), pos
         0
     };
 
-    if mid == 0 {
+    let pieces = if mid == 0 {
         let mid2 = if pos1.1 < pos2.1 {
             pos1.1 + 1
         } else if pos2.1 < pos1.1 {
@@ -734,24 +1244,24 @@ pub fn mut_pieces(board: &mut [[Option<Piece>; 8]; 8], pos1: (usize, usize), pos
             panic!(); // We are trying to get mutable references to the same memory location
         };
         let (first_half, last_half) = board[pos1.0].split_at_mut(mid2);
-        pieces = (&mut first_half[pos1.1], &mut last_half[pos2.1]);
+        (&mut first_half[pos1.1], &mut last_half[pos2.1])
     } else {
         let (first_half, last_half) = board.split_at_mut(mid);
-        pieces = (&mut first_half[pos1.0][pos1.1], &mut last_half[pos2.0][pos2.1]);
-    }
+        (&mut first_half[pos1.0][pos1.1], &mut last_half[pos2.0][pos2.1])
+    };
 
     pieces
 
 }
 
-pub fn display_board(board: &[[Option<Piece>; 8]; 8]) {
+pub fn display_board(board: &Board) {
 
     let mut display: String = "".to_string();
 
     for i in (0..8).rev() {
         display.push_str(&format!("{} ", i + 1));
-        for j in 0..8 {
-            match board[j][i] {
+        for file in board.iter() {
+            match file[i] {
                 Some(piece) => display.push(match (piece.piece_type, piece.color) {
                     (PieceType::Pawn, Color::White) => '♙',
                     (PieceType::Pawn, Color::Black) => '♟',
@@ -780,8 +1290,8 @@ pub fn display_board(board: &[[Option<Piece>; 8]; 8]) {
 
 pub fn parse_move(expr: &str) -> Option<((usize, usize), (usize, usize))> {
     let pos_str: Vec<&str> = expr.split(' ').collect();
-    if pos_str.len() != 2 {
-        return None;
+    if pos_str.len() != 2 || pos_str.iter().any(|pos| pos.chars().count() != 2) {
+        None
     } else {
         let start_pos_str: Vec<char> = pos_str[0].chars().collect();
         let start_pos_1 = match start_pos_str[0] {
@@ -829,244 +1339,153 @@ pub fn parse_move(expr: &str) -> Option<((usize, usize), (usize, usize))> {
             '8' => 7,
             _ => return None,
         } as usize;
-        return Some(((start_pos_1, start_pos_2), (end_pos_1, end_pos_2)));
+        Some(((start_pos_1, start_pos_2), (end_pos_1, end_pos_2)))
     }
 }
 
 // Return true if there is check
-pub fn check_check(board: &[[Option<Piece>; 8]; 8], start_pos: (usize, usize), end_pos: (usize, usize), king_pos: (usize, usize)) -> bool { // What a name ...
-    let mut temp_board = board.clone();
+pub fn check_check(board: &Board, start_pos: (usize, usize), end_pos: (usize, usize), king_pos: (usize, usize)) -> bool { // What a name ...
+    let mut temp_board = *board;
+    if let Some(piece) = temp_board[start_pos.0][start_pos.1] {
+        if piece.piece_type == PieceType::Pawn && start_pos.0 != end_pos.0 && temp_board[end_pos.0][end_pos.1].is_none() {
+            // En passant capture, the taken pawn leaves the board too
+            temp_board[end_pos.0][start_pos.1] = None;
+        }
+    }
     temp_board[end_pos.0][end_pos.1] = temp_board[start_pos.0][start_pos.1];
     temp_board[start_pos.0][start_pos.1] = None;
 
-    println!("TEMP BOARD :");
-    display_board(&temp_board);
-
-    let king_color = temp_board[king_pos.0][king_pos.1].unwrap().color;
-    let enemy_color = match king_color {
-        Color::White => Color::Black,
-        Color::Black => Color::White,
+    let king_color = match temp_board[king_pos.0][king_pos.1] {
+        Some(king) => king.color,
+        None => return false,
     };
 
-    let mut knight_pos: Vec<(isize, isize)> = vec![];
-    knight_pos.push((king_pos.0 as isize + 2, king_pos.0 as isize + 1));
-    knight_pos.push((king_pos.0 as isize + 2, king_pos.0 as isize - 1));
-    knight_pos.push((king_pos.0 as isize - 2, king_pos.0 as isize + 1));
-    knight_pos.push((king_pos.0 as isize - 2, king_pos.0 as isize - 1));
-    knight_pos.push((king_pos.0 as isize + 1, king_pos.0 as isize + 2));
-    knight_pos.push((king_pos.0 as isize - 1, king_pos.0 as isize + 2));
-    knight_pos.push((king_pos.0 as isize + 1, king_pos.0 as isize - 2));
-    knight_pos.push((king_pos.0 as isize - 1, king_pos.0 as isize - 2));
-    knight_pos = knight_pos.into_iter().filter(|pos| {
-        if pos.0 < 0 || pos.0 > 7 || pos.1 < 0 || pos.1 > 7 {
-            false
-        } else {
-            true
-        }
-    }).collect();
-    for pos in knight_pos {
-        match temp_board[pos.0 as usize][pos.1 as usize]  {
-            Some(piece) => if piece.piece_type == PieceType::Knight && piece.color == enemy_color {
-                return true;
-            } else {
-                continue;
-            },
-            None => continue,
-        }
-    }
-    match king_color {
-        Color::White => {
-            let mut pawn_pos = vec![(king_pos.0 as isize + 1, king_pos.1 as isize + 1), (king_pos.0 as isize - 1, king_pos.1 as isize + 1)];
-            
-            pawn_pos = pawn_pos.into_iter().filter(|pos| {
-                if pos.0 < 0 || pos.0 > 7 || pos.1 < 0 || pos.1 > 7 {
-                    false
-                } else {
-                    true
-                }
-            }).collect();
-
-            for pos in pawn_pos {
-                let pos = (pos.0 as usize, pos.1 as usize);
-                match temp_board[pos.0][pos.1] {
-                    Some(piece) => if piece.piece_type == PieceType::Pawn && piece.color == enemy_color {
-                        return true;
-                    },
-                    None => continue,
-                }
-            }
-        },
-        Color::Black => {
-            let mut pawn_pos = vec![(king_pos.0 as isize + 1, king_pos.1 as isize - 1), (king_pos.0 as isize - 1, king_pos.1 as isize - 1)];
-
-            pawn_pos = pawn_pos.into_iter().filter(|pos| {
-                if pos.0 < 0 || pos.0 > 7 || pos.1 < 0 || pos.1 > 7 {
-                    false
-                } else {
-                    true
-                }
-            }).collect();
+    is_attacked(&temp_board, king_pos, king_color.opponent())
+}
 
-            for pos in pawn_pos {
-                let pos = (pos.0 as usize, pos.1 as usize);
-                match temp_board[pos.0][pos.1] {
-                    Some(piece) => if piece.piece_type == PieceType::Pawn && piece.color == enemy_color {
-                        return true;
-                    },
-                    None => continue,
-                }
-            }
-        },
-    }
+// Return true if a piece of the given color could take on pos
+pub fn is_attacked(board: &Board, pos: (usize, usize), enemy_color: Color) -> bool {
+    let piece_at = |vec: (isize, isize)| -> Option<Piece> {
+        let target = (pos.0 as isize + vec.0, pos.1 as isize + vec.1);
+        if target.0 < 0 || target.0 > 7 || target.1 < 0 || target.1 > 7 {
+            return None;
+        }
+        board[target.0 as usize][target.1 as usize].filter(|piece| piece.color == enemy_color)
+    };
 
-    // let rook_pos = vec![(king_pos.0 + 1)..7, 0..king_pos.0, 0..king_pos.1, king_pos.1..7];
-    if !(((king_pos.0 + 1)..7).is_empty()) {
-        for i in (king_pos.0 + 1)..7 {
-            match temp_board[i][king_pos.1] {
-                Some(piece) => if (piece.piece_type == PieceType::Rook || piece.piece_type == PieceType::Queen) && piece.color == enemy_color {
-                    return true;
-                } else {
-                    break;
-                },
-                None => continue,
+    for vec in KNIGHT_VECS {
+        if let Some(piece) = piece_at(vec) {
+            if piece.piece_type == PieceType::Knight {
+                return true;
             }
         }
     }
-    if !((0..king_pos.0).is_empty()) {
-        for i in (0..king_pos.0).rev() {
-            match temp_board[i][king_pos.1] {
-                Some(piece) => if (piece.piece_type == PieceType::Rook || piece.piece_type == PieceType::Queen) && piece.color == enemy_color {
-                    return true;
-                } else {
-                    break;
-                },
-                None => continue,
+    for vec in KING_VECS {
+        if let Some(piece) = piece_at(vec) {
+            if piece.piece_type == PieceType::King {
+                return true;
             }
         }
     }
-    if !((0..king_pos.1).is_empty()) {
-        for i in (0..king_pos.1).rev() {
-            match temp_board[king_pos.0][i] {
-                Some(piece) => if (piece.piece_type == PieceType::Rook || piece.piece_type == PieceType::Queen) && piece.color == enemy_color {
-                    return true;
-                } else {
-                    break;
-                },
-                None => continue,
+    // Pawns take toward the enemy side, so look backward from their point of view
+    let pawn_dir = match enemy_color {
+        Color::White => -1,
+        Color::Black => 1,
+    };
+    for vec in [(-1, pawn_dir), (1, pawn_dir)] {
+        if let Some(piece) = piece_at(vec) {
+            if piece.piece_type == PieceType::Pawn {
+                return true;
             }
         }
     }
-    if !(((king_pos.1 + 1)..7).is_empty()) {
-        for i in (king_pos.1 + 1)..7 {
-            match temp_board[king_pos.0][i] {
-                Some(piece) => if (piece.piece_type == PieceType::Rook || piece.piece_type == PieceType::Queen) && piece.color == enemy_color {
-                    return true;
-                } else {
+
+    for (vecs, slider) in [(ROOK_VECS, PieceType::Rook), (BISHOP_VECS, PieceType::Bishop)] {
+        for vec in vecs {
+            let mut current = (pos.0 as isize + vec.0, pos.1 as isize + vec.1);
+            while (0..8).contains(&current.0) && (0..8).contains(&current.1) {
+                if let Some(piece) = board[current.0 as usize][current.1 as usize] {
+                    if piece.color == enemy_color && (piece.piece_type == slider || piece.piece_type == PieceType::Queen) {
+                        return true;
+                    }
                     break;
-                },
-                None => continue,
+                }
+                current = (current.0 + vec.0, current.1 + vec.1);
             }
         }
     }
 
-    let mut bishop_pos_pp: Vec<(usize, usize)> = vec![]; // positive, positive
-    let mut current_pos = king_pos.clone();
-    while current_pos.0 < 7 && current_pos.1 < 7 { // +, +
-        current_pos.0 += 1;
-        current_pos.1 += 1;
-        bishop_pos_pp.push(current_pos);
-    }
-    current_pos = king_pos.clone();
-    let mut bishop_pos_pn: Vec<(usize, usize)> = vec![]; // positive, negative
-    while current_pos.0 < 7 && current_pos.1 > 0 { // +, -
-        current_pos.0 += 1;
-        current_pos.1 -= 1;
-        bishop_pos_pn.push(current_pos);
-    }
-    current_pos = king_pos.clone();
-    let mut bishop_pos_np: Vec<(usize, usize)> = vec![]; // negative, positive
-    while current_pos.0 > 0 && current_pos.1 < 7 { // -, +
-        current_pos.0 -= 1;
-        current_pos.1 += 1;
-        bishop_pos_np.push(current_pos);
-    }
-    current_pos = king_pos.clone();
-    let mut bishop_pos_nn: Vec<(usize, usize)> = vec![]; // negative, negative
-    while current_pos.0 > 0 && current_pos.1 > 0 { // -, -
-        current_pos.0 -= 1;
-        current_pos.1 -= 1;
-        bishop_pos_nn.push(current_pos);
-    }
-    dbg!(&bishop_pos_pp);
-    dbg!(&bishop_pos_pn);
-    dbg!(&bishop_pos_np);
-    dbg!(&bishop_pos_nn);
-    println!("from ({}, {})", king_pos.0, king_pos.1);
-    for pos in bishop_pos_pp {
-        println!("to ({}, {})", pos.0, pos.1);
-        match temp_board[pos.0][pos.1] {
-            Some(piece) => if (piece.piece_type == PieceType::Bishop || piece.piece_type == PieceType::Queen) && piece.color == enemy_color {
-                return true;
-            } else {
-                break;
-            },
-            None => continue,
+    false
+}
+
+// Squares of the pieces of the given color that could take on pos, x-rays not included
+pub fn attackers(board: &Board, pos: (usize, usize), color: Color) -> Vec<(usize, usize)> {
+    let mut found = vec![];
+    let mut check = |vec: (isize, isize), piece_types: &[PieceType]| {
+        let target = (pos.0 as isize + vec.0, pos.1 as isize + vec.1);
+        if !(0..8).contains(&target.0) || !(0..8).contains(&target.1) {
+            return;
         }
-    }
-    for pos in bishop_pos_pn {
-        println!("to ({}, {})", pos.0, pos.1);
-        match temp_board[pos.0][pos.1] {
-            Some(piece) => if (piece.piece_type == PieceType::Bishop || piece.piece_type == PieceType::Queen) && piece.color == enemy_color {
-                return true;
-            } else {
-                break;
-            },
-            None => continue,
+        let target = (target.0 as usize, target.1 as usize);
+        if matches!(board[target.0][target.1], Some(piece) if piece.color == color && piece_types.contains(&piece.piece_type)) {
+            found.push(target);
         }
+    };
+
+    for vec in KNIGHT_VECS {
+        check(vec, &[PieceType::Knight]);
     }
-    for pos in bishop_pos_np {
-        println!("to ({}, {})", pos.0, pos.1);
-        match temp_board[pos.0][pos.1] {
-            Some(piece) => if (piece.piece_type == PieceType::Bishop || piece.piece_type == PieceType::Queen) && piece.color == enemy_color {
-                return true;
-            } else {
-                break;
-            },
-            None => continue,
-        }
+    for vec in KING_VECS {
+        check(vec, &[PieceType::King]);
     }
-    for pos in bishop_pos_nn {
-        println!("to ({}, {})", pos.0, pos.1);
-        match temp_board[pos.0][pos.1] {
-            Some(piece) => if (piece.piece_type == PieceType::Bishop || piece.piece_type == PieceType::Queen) && piece.color == enemy_color {
-                return true;
-            } else {
-                break;
-            },
-            None => continue,
+    let pawn_dir = match color {
+        Color::White => -1,
+        Color::Black => 1,
+    };
+    for vec in [(-1, pawn_dir), (1, pawn_dir)] {
+        check(vec, &[PieceType::Pawn]);
+    }
+
+    for (vecs, slider) in [(ROOK_VECS, PieceType::Rook), (BISHOP_VECS, PieceType::Bishop)] {
+        for vec in vecs {
+            let mut current = (pos.0 as isize + vec.0, pos.1 as isize + vec.1);
+            while (0..8).contains(&current.0) && (0..8).contains(&current.1) {
+                if let Some(piece) = board[current.0 as usize][current.1 as usize] {
+                    if piece.color == color && (piece.piece_type == slider || piece.piece_type == PieceType::Queen) {
+                        found.push((current.0 as usize, current.1 as usize));
+                    }
+                    break;
+                }
+                current = (current.0 + vec.0, current.1 + vec.1);
+            }
         }
     }
 
-    let mut enemy_king_pos = vec![(king_pos.0 as isize + 1, king_pos.1 as isize), (king_pos.0 as isize - 1, king_pos.1 as isize), (king_pos.0 as isize, king_pos.1 as isize + 1), (king_pos.0 as isize, king_pos.1 as isize - 1), (king_pos.0 as isize + 1, king_pos.1 as isize + 1), (king_pos.0 as isize - 1, king_pos.1 as isize + 1), (king_pos.0 as isize - 1, king_pos.1 as isize - 1), (king_pos.0 as isize + 1, king_pos.1 as isize - 1)];
+    found
+}
 
-    enemy_king_pos = enemy_king_pos.into_iter().filter(|pos| {
-        if pos.0 < 0 || pos.0 > 7 || pos.1 < 0 || pos.1 > 7 {
-            false
-        } else {
-            true
+// Return true if every square strictly between the two positions is empty
+pub fn path_is_clear(board: &Board, start_pos: (usize, usize), end_pos: (usize, usize)) -> bool {
+    let step = ((end_pos.0 as isize - start_pos.0 as isize).signum(), (end_pos.1 as isize - start_pos.1 as isize).signum());
+    let mut current = (start_pos.0 as isize + step.0, start_pos.1 as isize + step.1);
+    while current != (end_pos.0 as isize, end_pos.1 as isize) {
+        if board[current.0 as usize][current.1 as usize].is_some() {
+            return false;
         }
-    }).collect();
+        current = (current.0 + step.0, current.1 + step.1);
+    }
+    true
+}
 
-    for pos in enemy_king_pos {
-        match temp_board[pos.0 as usize][pos.1 as usize] {
-            Some(piece) => if piece.piece_type == PieceType::King && piece.color == enemy_color {
-                return true;
-            } else {
-                continue;
-            },
-            None => continue,
+pub fn find_king(board: &Board, color: Color) -> Option<(usize, usize)> {
+    for (i, file) in board.iter().enumerate() {
+        for (j, square) in file.iter().enumerate() {
+            if let Some(piece) = square {
+                if piece.piece_type == PieceType::King && piece.color == color {
+                    return Some((i, j));
+                }
+            }
         }
     }
-
-    return false;
-}
\ No newline at end of file
+    None
+}
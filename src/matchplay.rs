@@ -0,0 +1,637 @@
+use std::fmt::{self, Display};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::time::{Duration, Instant};
+
+use crate::epd::parse_epd;
+use crate::outcome::outcome;
+use crate::pgn::{Game, MoveNode};
+use crate::polyglot::{Book, BookSelection};
+use crate::position::{Move, Position, START_FEN};
+use crate::search::{Limits, SearchOptions, Searcher};
+use crate::skill::Skill;
+use crate::tablebase::{Tablebase, Wdl};
+use crate::uciclient::{UciEngine, UciError};
+use crate::{format_score, Color};
+
+pub const USAGE: &str = "Usage: chess --match engine=<spec> engine=<spec> [games=<n>] [tc=<seconds>+<increment>|st=<seconds>|depth=<n>|nodes=<n>] \
+[book=<file.bin> [plies=<n>]|epd=<file>] [pgn=<file>] [resign=<cp>/<moves>] [draw=<move>/<moves>/<cp>] [tb=<directory>] [sprt=<elo0>/<elo1>[/<alpha>/<beta>]]
+  spec: self[,<option>=<value>...] or uci:<command>[,<option>=<value>...]";
+
+// Extra time an external engine gets over its clock before it loses on time
+const TIME_MARGIN: Duration = Duration::from_millis(500);
+// How long an external engine searching to a depth or a number of nodes has for
+// its move, so that a hung engine forfeits instead of stalling the match
+const UNTIMED_TIMEOUT: Duration = Duration::from_secs(60);
+
+// One of the two engines of the match
+#[derive(Debug, Clone)]
+pub enum EngineConfig {
+    // This engine, with its own searcher and options
+    Internal { name: String, options: SearchOptions, threads: usize, hash: usize, skill: Skill },
+    // A UCI engine started with the command, then given the options
+    External { command: Vec<String>, options: Vec<(String, String)> },
+}
+
+impl EngineConfig {
+    // self,NullMove=off,Threads=2 or uci:/path/to/engine,Hash=64
+    fn parse(spec: &str) -> Result<Self, String> {
+        let mut fields = spec.split(',');
+        let kind = fields.next().unwrap_or("");
+        let settings: Vec<(String, String)> = fields.map(|field| match field.split_once('=') {
+            Some((name, value)) => Ok((name.to_string(), value.to_string())),
+            None => Err(format!("expected <option>=<value>, got {}", field)),
+        }).collect::<Result<_, _>>()?;
+
+        if let Some(command) = kind.strip_prefix("uci:") {
+            let command: Vec<String> = command.split_whitespace().map(str::to_string).collect();
+            if command.is_empty() {
+                return Err("uci: needs a command".to_string());
+            }
+            return Ok(EngineConfig::External { command, options: settings });
+        }
+        if kind != "self" {
+            return Err(format!("unknown engine {}, expected self or uci:<command>", kind));
+        }
+        let mut name = format!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
+        let (mut options, mut threads, mut hash, mut skill) = (SearchOptions::default(), 1, crate::search::DEFAULT_HASH_MB, Skill::default());
+        for (option, value) in &settings {
+            let number = value.parse::<u32>().map_err(|_| format!("invalid value {} for {}", value, option));
+            match option.as_str() {
+                "Threads" => threads = number? as usize,
+                "Hash" => hash = number? as usize,
+                "Skill" => skill = Skill::new(number?),
+                "Elo" => skill = Skill::from_elo(number?),
+                _ => {
+                    let enabled = match value.as_str() {
+                        "on" | "true" => true,
+                        "off" | "false" => false,
+                        _ => return Err(format!("invalid value {} for {}", value, option)),
+                    };
+                    if !options.set(option, enabled) {
+                        return Err(format!("unknown option {}", option));
+                    }
+                },
+            }
+            name.push_str(&format!(" {}={}", option, value));
+        }
+        Ok(EngineConfig::Internal { name, options, threads, hash, skill })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TimeControl {
+    // Base time for the game and increment per move
+    Clock { base: Duration, increment: Duration },
+    MoveTime(Duration),
+    Depth(u32),
+    Nodes(u64),
+}
+
+impl Display for TimeControl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TimeControl::Clock { base, increment } => write!(f, "{}+{}", base.as_secs_f64(), increment.as_secs_f64()),
+            TimeControl::MoveTime(movetime) => write!(f, "{}/move", movetime.as_secs_f64()),
+            TimeControl::Depth(depth) => write!(f, "depth {}", depth),
+            TimeControl::Nodes(nodes) => write!(f, "{} nodes", nodes),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub enum Openings {
+    StartPosition,
+    // Random weighted lines of a Polyglot book, up to a number of plies
+    Book { path: String, plies: usize },
+    // The positions of an EPD file, in turn
+    Epd(Vec<Position>),
+}
+
+// Sequential probability ratio test between two Elo differences
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Sprt {
+    pub elo0: f64,
+    pub elo1: f64,
+    pub alpha: f64,
+    pub beta: f64,
+}
+
+impl Sprt {
+    // The log-likelihood ratio is within these until a hypothesis is accepted
+    pub fn bounds(&self) -> (f64, f64) {
+        ((self.beta / (1.0 - self.alpha)).ln(), ((1.0 - self.beta) / self.alpha).ln())
+    }
+
+    // Log-likelihood ratio of elo1 against elo0 for the results so far, with the
+    // normal approximation of the trinomial distribution of the game scores
+    pub fn llr(&self, stats: &Stats) -> f64 {
+        let n = stats.games() as f64;
+        let (score, variance) = match stats.score_variance() {
+            Some(moments) if moments.1 > 0.0 => moments,
+            _ => return 0.0,
+        };
+        let expected = |elo: f64| 1.0 / (1.0 + 10f64.powf(-elo / 400.0));
+        let (s0, s1) = (expected(self.elo0), expected(self.elo1));
+        n * (s1 - s0) * (2.0 * score - s0 - s1) / (2.0 * variance)
+    }
+}
+
+pub struct MatchConfig {
+    pub engines: Vec<EngineConfig>,
+    pub games: usize,
+    pub time_control: TimeControl,
+    pub openings: Openings,
+    pub pgn: Option<String>,
+    // A side resigns once its score stayed below -cp for that many of its moves
+    pub resign: Option<(i32, u32)>,
+    // Draw from a move number, once both sides scored within cp for that many moves each
+    pub draw: Option<(u32, u32, i32)>,
+    pub tablebase: Option<String>,
+    pub sprt: Option<Sprt>,
+}
+
+fn numbers<T: std::str::FromStr>(value: &str, count: usize, name: &str) -> Result<Vec<T>, String> {
+    let numbers: Vec<T> = value.split('/').map(|number| number.parse::<T>()).collect::<Result<_, _>>().map_err(|_| format!("invalid {} {}", name, value))?;
+    if numbers.len() != count {
+        return Err(format!("invalid {} {}", name, value));
+    }
+    Ok(numbers)
+}
+
+fn seconds(value: &str) -> Result<Duration, String> {
+    value.parse::<f64>().ok().filter(|seconds| seconds.is_finite() && *seconds >= 0.0).map(Duration::from_secs_f64).ok_or_else(|| format!("invalid time {}", value))
+}
+
+impl MatchConfig {
+    // key=value arguments, see USAGE
+    pub fn parse(args: &[String]) -> Result<Self, String> {
+        let mut config = MatchConfig {
+            engines: vec![],
+            games: 10,
+            time_control: TimeControl::Clock { base: Duration::from_secs(10), increment: Duration::from_millis(100) },
+            openings: Openings::StartPosition,
+            pgn: None,
+            resign: None,
+            draw: None,
+            tablebase: None,
+            sprt: None,
+        };
+        let mut plies = 8;
+        for arg in args {
+            let (key, value) = arg.split_once('=').ok_or_else(|| format!("expected <key>=<value>, got {}", arg))?;
+            match key {
+                "engine" => config.engines.push(EngineConfig::parse(value)?),
+                "games" => config.games = value.parse().map_err(|_| format!("invalid number of games {}", value))?,
+                "tc" => {
+                    let (base, increment) = value.split_once('+').unwrap_or((value, "0"));
+                    config.time_control = TimeControl::Clock { base: seconds(base)?, increment: seconds(increment)? };
+                },
+                "st" => config.time_control = TimeControl::MoveTime(seconds(value)?),
+                "depth" => config.time_control = TimeControl::Depth(value.parse().map_err(|_| format!("invalid depth {}", value))?),
+                "nodes" => config.time_control = TimeControl::Nodes(value.parse().map_err(|_| format!("invalid nodes {}", value))?),
+                "book" => config.openings = Openings::Book { path: value.to_string(), plies },
+                "plies" => plies = value.parse().map_err(|_| format!("invalid plies {}", value))?,
+                "epd" => {
+                    let text = std::fs::read_to_string(value).map_err(|error| format!("can't read {}: {}", value, error))?;
+                    let positions: Vec<Position> = parse_epd(&text).into_iter().filter_map(Result::ok).map(|record| record.position).collect();
+                    if positions.is_empty() {
+                        return Err(format!("no position in {}", value));
+                    }
+                    config.openings = Openings::Epd(positions);
+                },
+                "pgn" => config.pgn = Some(value.to_string()),
+                "resign" => {
+                    let resign = numbers::<u32>(value, 2, "resign")?;
+                    let threshold = i32::try_from(resign[0]).map_err(|_| format!("invalid resign {}", value))?;
+                    config.resign = Some((threshold, resign[1]));
+                },
+                "draw" => {
+                    let draw = numbers::<u32>(value, 3, "draw")?;
+                    let threshold = i32::try_from(draw[2]).map_err(|_| format!("invalid draw {}", value))?;
+                    config.draw = Some((draw[0], draw[1], threshold));
+                },
+                "tb" => config.tablebase = Some(value.to_string()),
+                "sprt" => {
+                    let sprt = value.split('/').map(str::parse::<f64>).collect::<Result<Vec<f64>, _>>().map_err(|_| format!("invalid sprt {}", value))?;
+                    let sprt = match sprt[..] {
+                        [elo0, elo1] => Sprt { elo0, elo1, alpha: 0.05, beta: 0.05 },
+                        [elo0, elo1, alpha, beta] => Sprt { elo0, elo1, alpha, beta },
+                        _ => return Err(format!("invalid sprt {}", value)),
+                    };
+                    // Error rates are probabilities, and elo1 the better of the two hypotheses
+                    let probability = |p: f64| p > 0.0 && p < 1.0;
+                    if !(probability(sprt.alpha) && probability(sprt.beta) && sprt.elo0.is_finite() && sprt.elo1.is_finite() && sprt.elo0 < sprt.elo1) {
+                        return Err(format!("invalid sprt {}", value));
+                    }
+                    config.sprt = Some(sprt);
+                },
+                _ => return Err(format!("unknown setting {}", key)),
+            }
+        }
+        // plies may come after book
+        if let Openings::Book { plies: book_plies, .. } = &mut config.openings {
+            *book_plies = plies;
+        }
+        if config.engines.len() != 2 {
+            return Err("a match needs two engines".to_string());
+        }
+        Ok(config)
+    }
+}
+
+// Wins, losses and draws of the first engine
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Stats {
+    pub wins: u32,
+    pub losses: u32,
+    pub draws: u32,
+}
+
+fn elo(score: f64) -> f64 {
+    -400.0 * (1.0 / score - 1.0).log10()
+}
+
+impl Stats {
+    pub fn games(&self) -> u32 {
+        self.wins + self.losses + self.draws
+    }
+
+    // Mean score of a game and its variance
+    fn score_variance(&self) -> Option<(f64, f64)> {
+        let n = self.games() as f64;
+        if n == 0.0 {
+            return None;
+        }
+        let score = (self.wins as f64 + self.draws as f64 / 2.0) / n;
+        let deviation = |result: f64, count: u32| count as f64 * (result - score).powi(2);
+        Some((score, (deviation(1.0, self.wins) + deviation(0.0, self.losses) + deviation(0.5, self.draws)) / n))
+    }
+
+    // Elo difference and its 95% error margin, None while the score is 0 or 100%
+    pub fn elo(&self) -> Option<(f64, f64)> {
+        let (score, variance) = self.score_variance()?;
+        if score <= 0.0 || score >= 1.0 {
+            return None;
+        }
+        let error = 1.96 * (variance / self.games() as f64).sqrt();
+        let (low, high) = ((score - error).max(1e-6), (score + error).min(1.0 - 1e-6));
+        Some((elo(score), (elo(high) - elo(low)) / 2.0))
+    }
+}
+
+// An engine ready to play
+enum Player {
+    Internal(Box<Searcher>, String),
+    External(Box<UciEngine>),
+}
+
+// What a player answered: its move, its score for the side to move and its depth
+struct Answer {
+    mv: Option<Move>,
+    score: Option<i32>,
+    depth: u32,
+}
+
+impl Player {
+    fn start(config: &EngineConfig) -> Result<Self, String> {
+        match config {
+            EngineConfig::Internal { name, options, threads, hash, skill } => {
+                let mut searcher = Searcher::new();
+                searcher.set_options(*options);
+                searcher.set_threads(*threads);
+                searcher.set_hash_size(*hash);
+                searcher.set_skill(*skill);
+                Ok(Player::Internal(Box::new(searcher), name.clone()))
+            },
+            EngineConfig::External { command, options } => {
+                let args: Vec<&str> = command[1..].iter().map(String::as_str).collect();
+                let mut engine = UciEngine::spawn(&command[0], &args).map_err(|error| format!("can't start {}: {}", command[0], error))?;
+                for (name, value) in options {
+                    engine.set_option(name, value).map_err(|error| error.to_string())?;
+                }
+                Ok(Player::External(Box::new(engine)))
+            },
+        }
+    }
+
+    fn name(&self) -> &str {
+        match self {
+            Player::Internal(_, name) => name,
+            Player::External(engine) => &engine.name,
+        }
+    }
+
+    fn new_game(&mut self) -> Result<(), String> {
+        match self {
+            Player::Internal(searcher, _) => {
+                searcher.clear_hash();
+                Ok(())
+            },
+            Player::External(engine) => engine.new_game().map_err(|error| error.to_string()),
+        }
+    }
+
+    // The move for the last of the positions, reached by the moves from the first
+    fn think(&mut self, positions: &[Position], moves: &[Move], limits: Limits, timeout: Option<Duration>) -> Result<Answer, UciError> {
+        let (position, previous) = positions.split_last().unwrap_or_else(|| unreachable!());
+        match self {
+            Player::Internal(searcher, _) => {
+                searcher.set_game_history(previous);
+                let result = searcher.search(position, limits);
+                Ok(Answer { mv: result.best_move, score: Some(result.score), depth: result.depth })
+            },
+            Player::External(engine) => {
+                engine.set_position(&positions[0], moves)?;
+                engine.go(&limits)?;
+                let best = engine.wait_best_move(timeout, |_| ()).inspect_err(|error| {
+                    // The late bestmove is skipped when the next game waits for readyok
+                    if matches!(error, UciError::Timeout) {
+                        let _ = engine.stop();
+                    }
+                })?;
+                Ok(Answer { mv: best.best_move, score: best.info.score, depth: best.info.depth.unwrap_or(0) })
+            },
+        }
+    }
+
+    fn quit(self) {
+        if let Player::External(engine) = self {
+            engine.quit();
+        }
+    }
+}
+
+// A finished game
+struct GameRecord {
+    positions: Vec<Position>,
+    moves: Vec<MoveNode>,
+    result: &'static str,
+    reason: String,
+    termination: &'static str,
+}
+
+fn color_name(color: Color) -> &'static str {
+    match color {
+        Color::White => "White",
+        Color::Black => "Black",
+    }
+}
+
+fn loss(color: Color) -> &'static str {
+    match color {
+        Color::White => "0-1",
+        Color::Black => "1-0",
+    }
+}
+
+fn opening(openings: &Openings, book: &mut Option<Book<File>>, pair: usize) -> (Position, Vec<Move>) {
+    match openings {
+        Openings::StartPosition => (Position::new(), vec![]),
+        Openings::Epd(positions) => (positions[pair % positions.len()], vec![]),
+        Openings::Book { plies, .. } => {
+            let mut position = Position::new();
+            let mut moves = vec![];
+            while moves.len() < *plies {
+                let mv = match book.as_mut().and_then(|book| book.choose_move(&position, BookSelection::Weighted).ok().flatten()) {
+                    Some(mv) => mv,
+                    None => break,
+                };
+                position.make_move(mv);
+                moves.push(mv);
+            }
+            (Position::new(), moves)
+        },
+    }
+}
+
+struct Match<'a> {
+    config: &'a MatchConfig,
+    tablebase: Option<Tablebase>,
+}
+
+impl Match<'_> {
+    // Pieces few enough for the tablebases decide the game
+    fn tablebase_result(&self, position: &Position) -> Option<(&'static str, String)> {
+        let tablebase = self.tablebase.as_ref()?;
+        let pieces = position.board.iter().flatten().flatten().count();
+        if pieces > tablebase.max_pieces() {
+            return None;
+        }
+        let result = match tablebase.probe_wdl(position)? {
+            Wdl::Win => match position.side_to_move {
+                Color::White => "1-0",
+                Color::Black => "0-1",
+            },
+            Wdl::Loss => loss(position.side_to_move),
+            Wdl::Draw | Wdl::CursedWin | Wdl::BlessedLoss => "1/2-1/2",
+        };
+        Some((result, "Tablebase adjudication".to_string()))
+    }
+
+    fn play(&self, white: &mut Player, black: &mut Player, start: Position, opening: &[Move]) -> GameRecord {
+        let mut positions = vec![start];
+        let mut moves = vec![];
+        let mut played = vec![];
+        for &mv in opening {
+            let position = positions[positions.len() - 1];
+            moves.push(MoveNode { mv, san: position.san(mv), nags: vec![], starting_comment: None, comments: vec!["book".to_string()], variations: vec![] });
+            played.push(mv);
+            let mut next = position;
+            next.make_move(mv);
+            positions.push(next);
+        }
+        let mut clocks = match self.config.time_control {
+            TimeControl::Clock { base, .. } => [Some(base); 2],
+            _ => [None; 2],
+        };
+        // Moves in a row that each side wanted to resign on, and plies in a row with a drawish score
+        let mut resigning = [0; 2];
+        let mut drawish = 0;
+
+        let end = |result: &'static str, reason: String, termination: &'static str, positions: Vec<Position>, moves: Vec<MoveNode>| GameRecord { positions, moves, result, reason, termination };
+        for player in [&mut *white, &mut *black] {
+            if let Err(error) = player.new_game() {
+                return end("*", format!("{}: {}", player.name(), error), "abandoned", positions, moves);
+            }
+        }
+        loop {
+            let position = positions[positions.len() - 1];
+            if let Some(outcome) = outcome(&positions) {
+                return end(outcome.result(), outcome.to_string(), "normal", positions, moves);
+            }
+            if let Some((result, reason)) = self.tablebase_result(&position) {
+                return end(result, reason, "adjudication", positions, moves);
+            }
+
+            let side = position.side_to_move;
+            let index = if side == Color::White { 0 } else { 1 };
+            let player = if side == Color::White { &mut *white } else { &mut *black };
+            let limits = match self.config.time_control {
+                TimeControl::Clock { increment, .. } => Limits { wtime: clocks[0], btime: clocks[1], winc: increment, binc: increment, ..Limits::default() },
+                TimeControl::MoveTime(movetime) => Limits::movetime(movetime),
+                TimeControl::Depth(depth) => Limits::depth(depth),
+                TimeControl::Nodes(nodes) => Limits { nodes: Some(nodes), ..Limits::default() },
+            };
+            let timeout = match self.config.time_control {
+                TimeControl::Clock { .. } => clocks[index].map(|clock| clock + TIME_MARGIN),
+                TimeControl::MoveTime(movetime) => Some(movetime + TIME_MARGIN),
+                TimeControl::Depth(_) | TimeControl::Nodes(_) => Some(UNTIMED_TIMEOUT),
+            };
+
+            let started = Instant::now();
+            let answer = player.think(&positions, &played, limits, timeout);
+            let elapsed = started.elapsed();
+            let answer = match answer {
+                Ok(answer) => answer,
+                Err(UciError::Timeout) => return end(loss(side), format!("{} loses on time", color_name(side)), "time forfeit", positions, moves),
+                Err(error) => return end(loss(side), format!("{}'s engine failed: {}", color_name(side), error), "abandoned", positions, moves),
+            };
+            if let (TimeControl::Clock { increment, .. }, Some(clock)) = (self.config.time_control, clocks[index]) {
+                if elapsed > clock {
+                    return end(loss(side), format!("{} loses on time", color_name(side)), "time forfeit", positions, moves);
+                }
+                clocks[index] = Some(clock - elapsed + increment);
+            }
+            let mv = match answer.mv.filter(|&mv| position.is_legal(mv)) {
+                Some(mv) => mv,
+                None => return end(loss(side), format!("{} makes an illegal move", color_name(side)), "rules infraction", positions, moves),
+            };
+
+            let comment = match answer.score {
+                Some(score) => format!("{}/{} {:.2}s", format_score(score), answer.depth, elapsed.as_secs_f64()),
+                None => format!("{:.2}s", elapsed.as_secs_f64()),
+            };
+            moves.push(MoveNode { mv, san: position.san(mv), nags: vec![], starting_comment: None, comments: vec![comment], variations: vec![] });
+            played.push(mv);
+            let mut next = position;
+            next.make_move(mv);
+            positions.push(next);
+
+            if let (Some((threshold, count)), Some(score)) = (self.config.resign, answer.score) {
+                resigning[index] = if score <= -threshold { resigning[index] + 1 } else { 0 };
+                if resigning[index] >= count {
+                    return end(loss(side), format!("{} resigns", color_name(side)), "adjudication", positions, moves);
+                }
+            }
+            if let Some((from, count, threshold)) = self.config.draw {
+                let in_range = position.fullmove_number >= from && answer.score.is_some_and(|score| score.abs() <= threshold);
+                drawish = if in_range { drawish + 1 } else { 0 };
+                if drawish >= 2 * count {
+                    return end("1/2-1/2", "Draw by adjudication".to_string(), "adjudication", positions, moves);
+                }
+            }
+        }
+    }
+
+    fn pgn(&self, record: &mut GameRecord, round: usize, white: &str, black: &str) -> String {
+        let mut game = Game::default();
+        let fen = record.positions[0].to_fen();
+        for (name, value) in [("Event", "Engine match"), ("Site", "?"), ("Date", "????.??.??"), ("Round", &round.to_string()), ("White", white), ("Black", black), ("Result", record.result)] {
+            game.set_tag(name, value);
+        }
+        if fen != START_FEN {
+            game.set_tag("SetUp", "1");
+            game.set_tag("FEN", &fen);
+        }
+        game.set_tag("TimeControl", &self.config.time_control.to_string());
+        game.set_tag("Termination", record.termination);
+        if let Some(last) = record.moves.last_mut() {
+            last.comments.push(record.reason.clone());
+        }
+        game.moves = std::mem::take(&mut record.moves);
+        game.result = Some(record.result.to_string());
+        game.to_pgn()
+    }
+}
+
+// Plays the match, printing each result with the score, Elo and SPRT so far
+pub fn run(config: &MatchConfig) -> Result<Stats, String> {
+    let tablebase = match &config.tablebase {
+        Some(path) => Some(Tablebase::open(path).map_err(|error| format!("can't open tablebases in {}: {}", path, error))?),
+        None => None,
+    };
+    let mut book = match &config.openings {
+        Openings::Book { path, .. } => Some(Book::open(path).map_err(|error| format!("can't open {}: {}", path, error))?),
+        _ => None,
+    };
+    let mut pgn = match &config.pgn {
+        Some(path) => Some(OpenOptions::new().create(true).append(true).open(path).map_err(|error| format!("can't open {}: {}", path, error))?),
+        None => None,
+    };
+    let mut players = [Player::start(&config.engines[0])?, Player::start(&config.engines[1])?];
+    let names = [players[0].name().to_string(), players[1].name().to_string()];
+    let runner = Match { config, tablebase };
+    let mut stats = Stats::default();
+    println!("{} vs {}, {} games at {}", names[0], names[1], config.games, config.time_control);
+
+    let mut start = (Position::new(), vec![]);
+    for round in 0..config.games {
+        // Each opening is played twice, the engines swapping colors
+        if round % 2 == 0 {
+            start = opening(&config.openings, &mut book, round / 2);
+        }
+        let first_is_white = round % 2 == 0;
+        let [first, second] = &mut players;
+        let (white, black) = if first_is_white { (first, second) } else { (second, first) };
+        let mut record = runner.play(white, black, start.0, &start.1);
+        let (white_name, black_name) = if first_is_white { (&names[0], &names[1]) } else { (&names[1], &names[0]) };
+        println!("Game {} ({} vs {}): {} {{{}}}", round + 1, white_name, black_name, record.result, record.reason);
+
+        match (record.result, first_is_white) {
+            ("1-0", true) | ("0-1", false) => stats.wins += 1,
+            ("0-1", true) | ("1-0", false) => stats.losses += 1,
+            ("1/2-1/2", _) => stats.draws += 1,
+            _ => (),
+        }
+        if let Some(file) = &mut pgn {
+            let text = runner.pgn(&mut record, round + 1, white_name, black_name);
+            if let Err(error) = writeln!(file, "{}", text) {
+                println!("Can't write the game: {}", error);
+            }
+        }
+
+        let score = (stats.wins as f64 + stats.draws as f64 / 2.0) / stats.games().max(1) as f64;
+        println!("Score of {} vs {}: {} - {} - {} [{:.3}] {}", names[0], names[1], stats.wins, stats.losses, stats.draws, score, stats.games());
+        match stats.elo() {
+            Some((elo, error)) => println!("Elo difference: {:.1} +/- {:.1}", elo, error),
+            None => println!("Elo difference: unknown"),
+        }
+        if let Some(sprt) = &config.sprt {
+            let llr = sprt.llr(&stats);
+            let (lower, upper) = sprt.bounds();
+            println!("SPRT: llr {:.2} ({:.2}, {:.2}) [{}, {}]", llr, lower, upper, sprt.elo0, sprt.elo1);
+            if llr >= upper || llr <= lower {
+                println!("{} accepted", if llr >= upper { "H1" } else { "H0" });
+                break;
+            }
+        }
+        // A broken engine ends the match
+        if record.termination == "abandoned" {
+            break;
+        }
+    }
+    for player in players {
+        player.quit();
+    }
+    Ok(stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sprt(value: &str) -> Result<Option<Sprt>, String> {
+        let args = ["engine=self", "engine=self,NullMove=off", &format!("sprt={}", value)].map(str::to_string);
+        MatchConfig::parse(&args).map(|config| config.sprt)
+    }
+
+    #[test]
+    fn sprt_settings_are_checked() {
+        assert_eq!(sprt("0/5"), Ok(Some(Sprt { elo0: 0.0, elo1: 5.0, alpha: 0.05, beta: 0.05 })));
+        assert_eq!(sprt("-2/3/0.1/0.2"), Ok(Some(Sprt { elo0: -2.0, elo1: 3.0, alpha: 0.1, beta: 0.2 })));
+        for value in ["5/0", "5/5", "0/5/0/0.05", "0/5/0.05/1", "0/5/1.5/0.05", "0/5/-0.1/0.05", "0/NaN", "0/5/0.05"] {
+            assert_eq!(sprt(value), Err(format!("invalid sprt {}", value)));
+        }
+    }
+}
@@ -0,0 +1,211 @@
+use crate::eval::piece_value;
+use crate::position::{Move, Position};
+use crate::search::MAX_PLY;
+use crate::see::see;
+use crate::{Color, PieceType};
+
+// History scores stay within this, so that recent results weigh more than old ones
+const MAX_HISTORY: i32 = 16384;
+
+fn square(pos: (usize, usize)) -> usize {
+    pos.1 * 8 + pos.0
+}
+
+fn color_index(color: Color) -> usize {
+    match color {
+        Color::White => 0,
+        Color::Black => 1,
+    }
+}
+
+// Captures and queen promotions, the moves that change the material
+pub fn is_tactical(position: &Position, mv: Move) -> bool {
+    position.is_capture(mv) || mv.promotion == Some(PieceType::Queen)
+}
+
+// Most valuable victim first, then least valuable attacker
+pub fn mvv_lva(position: &Position, mv: Move) -> i32 {
+    let promotion = mv.promotion.map_or(0, piece_value);
+    let capture = match position.piece_at(mv.end_pos) {
+        Some(victim) => 10 * piece_value(victim.piece_type) - position.piece_at(mv.start_pos).map_or(0, |attacker| piece_value(attacker.piece_type)) / 10,
+        None if position.is_capture(mv) => 10 * piece_value(PieceType::Pawn),
+        None => 0,
+    };
+    promotion + capture
+}
+
+// What the search learnt about quiet moves: the ones that caused cutoffs at each
+// ply, in reply to each move, and overall for each side
+pub struct Heuristics {
+    killers: Vec<[Option<Move>; 2]>,
+    countermoves: Vec<[Option<Move>; 64]>,
+    history: Vec<[[i32; 64]; 64]>,
+}
+
+impl Default for Heuristics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Heuristics {
+    pub fn new() -> Self {
+        Heuristics { killers: vec![[None; 2]; MAX_PLY + 1], countermoves: vec![[None; 64]; 64], history: vec![[[0; 64]; 64]; 2] }
+    }
+
+    pub fn clear(&mut self) {
+        *self = Self::new();
+    }
+
+    // Killers only make sense in the tree they were found in, history fades
+    pub fn new_search(&mut self) {
+        self.killers.iter_mut().for_each(|killers| *killers = [None; 2]);
+        self.history.iter_mut().flatten().flatten().for_each(|score| *score /= 2);
+    }
+
+    pub fn killers(&self, ply: usize) -> [Option<Move>; 2] {
+        self.killers[ply]
+    }
+
+    pub fn countermove(&self, previous: Option<Move>) -> Option<Move> {
+        previous.and_then(|previous| self.countermoves[square(previous.start_pos)][square(previous.end_pos)])
+    }
+
+    pub fn history(&self, color: Color, mv: Move) -> i32 {
+        self.history[color_index(color)][square(mv.start_pos)][square(mv.end_pos)]
+    }
+
+    fn add_history(&mut self, color: Color, mv: Move, bonus: i32) {
+        let score = &mut self.history[color_index(color)][square(mv.start_pos)][square(mv.end_pos)];
+        *score += bonus - *score * bonus.abs() / MAX_HISTORY;
+    }
+
+    // A quiet move caused a cutoff, the quiet moves tried before it did not
+    pub fn update(&mut self, color: Color, ply: usize, depth: u32, best: Move, previous: Option<Move>, tried: &[Move]) {
+        if self.killers[ply][0] != Some(best) {
+            self.killers[ply] = [Some(best), self.killers[ply][0]];
+        }
+        if let Some(previous) = previous {
+            self.countermoves[square(previous.start_pos)][square(previous.end_pos)] = Some(best);
+        }
+        let bonus = (depth * depth).min(400) as i32;
+        self.add_history(color, best, bonus);
+        for &mv in tried.iter().filter(|&&mv| mv != best) {
+            self.add_history(color, mv, -bonus);
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Stage {
+    HashMove,
+    Generate,
+    GoodCaptures,
+    Killers,
+    Countermove,
+    Quiets,
+    BadCaptures,
+    Done,
+}
+
+// Hands out the moves of a position best first, a stage at a time, so that the
+// moves are only generated when the hash move did not cause a cutoff
+pub struct MovePicker {
+    stage: Stage,
+    hash_move: Option<Move>,
+    killers: [Option<Move>; 2],
+    countermove: Option<Move>,
+    good_captures: Vec<(Move, i32)>,
+    quiets: Vec<(Move, i32)>,
+    bad_captures: Vec<(Move, i32)>,
+}
+
+// Takes the highest scored move out of the list
+fn pick_best(moves: &mut Vec<(Move, i32)>) -> Option<Move> {
+    let best = (0..moves.len()).max_by_key(|&i| moves[i].1)?;
+    Some(moves.swap_remove(best).0)
+}
+
+// Takes the move out of the list if it is there
+fn take(moves: &mut Vec<(Move, i32)>, mv: Option<Move>) -> Option<Move> {
+    let index = moves.iter().position(|&(other, _)| Some(other) == mv)?;
+    Some(moves.swap_remove(index).0)
+}
+
+impl MovePicker {
+    pub fn new(hash_move: Option<Move>, killers: [Option<Move>; 2], countermove: Option<Move>) -> Self {
+        MovePicker { stage: Stage::HashMove, hash_move, killers, countermove, good_captures: vec![], quiets: vec![], bad_captures: vec![] }
+    }
+
+    pub fn stage(&self) -> Stage {
+        self.stage
+    }
+
+    fn generate(&mut self, position: &Position, heuristics: &Heuristics) {
+        for mv in position.legal_moves() {
+            if Some(mv) == self.hash_move {
+                continue;
+            }
+            if is_tactical(position, mv) {
+                let score = mvv_lva(position, mv);
+                if see(position, mv) >= 0 {
+                    self.good_captures.push((mv, score));
+                } else {
+                    self.bad_captures.push((mv, score));
+                }
+            } else {
+                // Underpromotions go last
+                let score = match mv.promotion {
+                    Some(_) => -2 * MAX_HISTORY,
+                    None => heuristics.history(position.side_to_move, mv),
+                };
+                self.quiets.push((mv, score));
+            }
+        }
+    }
+
+    pub fn next(&mut self, position: &Position, heuristics: &Heuristics) -> Option<Move> {
+        loop {
+            match self.stage {
+                Stage::HashMove => {
+                    self.stage = Stage::Generate;
+                    self.hash_move = self.hash_move.filter(|&mv| position.is_legal(mv));
+                    if self.hash_move.is_some() {
+                        return self.hash_move;
+                    }
+                },
+                Stage::Generate => {
+                    self.generate(position, heuristics);
+                    self.stage = Stage::GoodCaptures;
+                },
+                Stage::GoodCaptures => match pick_best(&mut self.good_captures) {
+                    Some(mv) => return Some(mv),
+                    None => self.stage = Stage::Killers,
+                },
+                Stage::Killers => match self.killers.iter_mut().find_map(Option::take) {
+                    Some(killer) => {
+                        if let Some(mv) = take(&mut self.quiets, Some(killer)) {
+                            return Some(mv);
+                        }
+                    },
+                    None => self.stage = Stage::Countermove,
+                },
+                Stage::Countermove => {
+                    self.stage = Stage::Quiets;
+                    if let Some(mv) = take(&mut self.quiets, self.countermove) {
+                        return Some(mv);
+                    }
+                },
+                Stage::Quiets => match pick_best(&mut self.quiets) {
+                    Some(mv) => return Some(mv),
+                    None => self.stage = Stage::BadCaptures,
+                },
+                Stage::BadCaptures => match pick_best(&mut self.bad_captures) {
+                    Some(mv) => return Some(mv),
+                    None => self.stage = Stage::Done,
+                },
+                Stage::Done => return None,
+            }
+        }
+    }
+}
@@ -0,0 +1,66 @@
+use std::fmt::{self, Display};
+
+use crate::polyglot::polyglot_key;
+use crate::position::Position;
+use crate::search::insufficient_material;
+use crate::Color;
+
+// How a game ended by the rules
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Outcome {
+    // The winner
+    Checkmate(Color),
+    Stalemate,
+    FiftyMoves,
+    Repetition,
+    InsufficientMaterial,
+}
+
+impl Outcome {
+    // The result as written in PGN
+    pub fn result(self) -> &'static str {
+        match self {
+            Outcome::Checkmate(Color::White) => "1-0",
+            Outcome::Checkmate(Color::Black) => "0-1",
+            _ => "1/2-1/2",
+        }
+    }
+}
+
+impl Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let reason = match self {
+            Outcome::Checkmate(Color::White) => "White mates",
+            Outcome::Checkmate(Color::Black) => "Black mates",
+            Outcome::Stalemate => "Stalemate",
+            Outcome::FiftyMoves => "Draw by fifty move rule",
+            Outcome::Repetition => "Draw by repetition",
+            Outcome::InsufficientMaterial => "Draw by insufficient material",
+        };
+        write!(f, "{}", reason)
+    }
+}
+
+// How the game is over after the last of its positions, if it is
+pub fn outcome(positions: &[Position]) -> Option<Outcome> {
+    let position = positions.last()?;
+    if position.is_checkmate() {
+        return Some(Outcome::Checkmate(position.side_to_move.opponent()));
+    }
+    if position.is_stalemate() {
+        return Some(Outcome::Stalemate);
+    }
+    if position.halfmove_clock >= 100 {
+        return Some(Outcome::FiftyMoves);
+    }
+    // Only positions since the last capture or pawn move can come back
+    let key = polyglot_key(position);
+    let since = positions.iter().rev().take(position.halfmove_clock as usize + 1);
+    if since.filter(|&other| polyglot_key(other) == key).count() >= 3 {
+        return Some(Outcome::Repetition);
+    }
+    if insufficient_material(position) {
+        return Some(Outcome::InsufficientMaterial);
+    }
+    None
+}
@@ -0,0 +1,624 @@
+use std::fmt::{self, Display};
+use std::io::{BufRead, BufReader, Read};
+use std::iter::Peekable;
+use std::str::Chars;
+
+use crate::eco::{self, Opening};
+use crate::position::{Move, Position};
+use crate::Color;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PgnError {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl Display for PgnError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}, column {}: {}", self.line, self.column, self.message)
+    }
+}
+
+impl std::error::Error for PgnError {}
+
+#[derive(Debug, Clone)]
+pub struct MoveNode {
+    pub mv: Move,
+    pub san: String,
+    pub nags: Vec<u8>,
+    // Comment found before the move, only set on the first move of a line
+    pub starting_comment: Option<String>,
+    pub comments: Vec<String>,
+    // Alternatives to this move, each one starting from the position before it
+    pub variations: Vec<Vec<MoveNode>>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Game {
+    pub tags: Vec<(String, String)>,
+    pub moves: Vec<MoveNode>,
+    pub result: Option<String>,
+}
+
+impl Game {
+    pub fn tag(&self, name: &str) -> Option<&str> {
+        self.tags.iter().find(|(tag_name, _)| tag_name == name).map(|(_, value)| value.as_str())
+    }
+
+    // The position the movetext starts from, given by the FEN tag if any
+    pub fn start_position(&self) -> Position {
+        match self.tag("FEN") {
+            // The FEN tag is checked while parsing
+            Some(fen) => Position::from_fen(fen).unwrap_or_default(),
+            None => Position::new(),
+        }
+    }
+
+    // Every position of the main line, starting with start_position
+    pub fn positions(&self) -> Vec<Position> {
+        let mut position = self.start_position();
+        let mut positions = vec![position];
+        for node in &self.moves {
+            position.make_move(node.mv);
+            positions.push(position);
+        }
+        positions
+    }
+
+    pub fn set_tag(&mut self, name: &str, value: &str) {
+        match self.tags.iter_mut().find(|(tag_name, _)| tag_name == name) {
+            Some((_, old_value)) => *old_value = value.to_string(),
+            None => self.tags.push((name.to_string(), value.to_string())),
+        }
+    }
+
+    // Fill the ECO, Opening and Variation tags from the main line
+    pub fn classify_opening(&mut self) -> Option<Opening> {
+        let opening = eco::classify(&self.positions())?;
+        self.set_tag("ECO", opening.eco);
+        self.set_tag("Opening", opening.name);
+        match opening.variation {
+            Some(variation) => self.set_tag("Variation", variation),
+            None => self.tags.retain(|(name, _)| name != "Variation"),
+        }
+        Some(opening)
+    }
+
+    pub fn to_pgn(&self) -> String {
+        let mut pgn = String::new();
+        for (name, value) in &self.tags {
+            pgn.push_str(&format!("[{} \"{}\"]\n", name, value.replace('\\', "\\\\").replace('"', "\\\"")));
+        }
+        pgn.push('\n');
+
+        let mut tokens = vec![];
+        movetext_tokens(&self.moves, self.start_position(), &mut tokens);
+        tokens.push(self.result.clone().or_else(|| self.tag("Result").map(|result| result.to_string())).unwrap_or_else(|| "*".to_string()));
+
+        // Lines of at most 80 characters, parentheses stuck to what they enclose
+        let mut line_length = 0;
+        for (i, token) in tokens.iter().enumerate() {
+            if i > 0 && tokens[i - 1] != "(" && token != ")" {
+                if line_length + 1 + token.len() > 80 {
+                    pgn.push('\n');
+                    line_length = 0;
+                } else {
+                    pgn.push(' ');
+                    line_length += 1;
+                }
+            }
+            pgn.push_str(token);
+            line_length += token.len();
+        }
+        pgn.push('\n');
+        pgn
+    }
+}
+
+fn movetext_tokens(nodes: &[MoveNode], mut position: Position, tokens: &mut Vec<String>) {
+    // Black moves get their number back after anything that interrupts the line
+    let mut needs_number = true;
+    for node in nodes {
+        if let Some(comment) = &node.starting_comment {
+            tokens.push(format!("{{{}}}", comment));
+        }
+        match position.side_to_move {
+            Color::White => tokens.push(format!("{}.", position.fullmove_number)),
+            Color::Black if needs_number => tokens.push(format!("{}...", position.fullmove_number)),
+            Color::Black => (),
+        }
+        tokens.push(node.san.clone());
+        tokens.extend(node.nags.iter().map(|nag| format!("${}", nag)));
+        tokens.extend(node.comments.iter().map(|comment| format!("{{{}}}", comment)));
+        for variation in &node.variations {
+            tokens.push("(".to_string());
+            movetext_tokens(variation, position, tokens);
+            tokens.push(")".to_string());
+        }
+        needs_number = !node.comments.is_empty() || !node.variations.is_empty();
+        position.make_move(node.mv);
+    }
+}
+
+pub fn parse_games(text: &str) -> Result<Vec<Game>, PgnError> {
+    let mut parser = Parser::new(text, 1);
+    let mut games = vec![];
+    while let Some(game) = parser.next_game()? {
+        games.push(game);
+    }
+    Ok(games)
+}
+
+// Reads a PGN database one game at a time, only keeping the current game in memory.
+// A malformed game yields an error and reading goes on with the next one.
+pub struct PgnReader<R> {
+    reader: R,
+    line: usize,
+    headers_only: bool,
+    // First line of the next game, read while looking for the end of the current one
+    next_line: Option<(usize, String)>,
+    done: bool,
+}
+
+impl<R: Read> PgnReader<BufReader<R>> {
+    pub fn new(reader: R) -> Self {
+        PgnReader::from_buf_read(BufReader::new(reader))
+    }
+}
+
+impl<R: BufRead> PgnReader<R> {
+    pub fn from_buf_read(reader: R) -> Self {
+        PgnReader { reader, line: 0, headers_only: false, next_line: None, done: false }
+    }
+
+    // Only parse the tag pairs, the moves are neither stored nor replayed
+    pub fn headers_only(mut self, headers_only: bool) -> Self {
+        self.headers_only = headers_only;
+        self
+    }
+
+    fn read_line(&mut self) -> Result<Option<(usize, String)>, PgnError> {
+        let mut bytes = vec![];
+        match self.reader.read_until(b'\n', &mut bytes) {
+            Ok(0) => Ok(None),
+            Ok(_) => {
+                self.line += 1;
+                // Old databases are often Latin-1, don't give up on them
+                let text = String::from_utf8_lossy(&bytes).trim_end_matches(['\n', '\r']).to_string();
+                Ok(Some((self.line, text)))
+            },
+            Err(e) => Err(PgnError { line: self.line + 1, column: 1, message: format!("read error: {}", e) }),
+        }
+    }
+
+    // Text of the next game with the number of its first line
+    fn next_chunk(&mut self) -> Result<Option<(usize, String)>, PgnError> {
+        let mut chunk = String::new();
+        let mut first_line = 0;
+        let mut in_movetext = false;
+        let mut in_comment = false;
+        let mut after_blank = false;
+
+        loop {
+            let (number, text) = match self.next_line.take() {
+                Some(line) => line,
+                None => match self.read_line()? {
+                    Some(line) => line,
+                    None => break,
+                },
+            };
+            let trimmed = text.trim_start();
+            let starts_tag = trimmed.starts_with('[');
+            // An unterminated comment or a game without movetext must not swallow the next games
+            let new_game = trimmed.starts_with("[Event ") && (in_comment || (after_blank && first_line != 0));
+            if (starts_tag && in_movetext && !in_comment) || new_game {
+                self.next_line = Some((number, text));
+                break;
+            }
+            if first_line == 0 {
+                if trimmed.is_empty() {
+                    continue;
+                }
+                first_line = number;
+            }
+            after_blank = trimmed.is_empty();
+            if !starts_tag && !trimmed.is_empty() && !trimmed.starts_with('%') {
+                in_movetext = true;
+            }
+            if in_movetext {
+                for c in text.chars() {
+                    if in_comment {
+                        in_comment = c != '}';
+                    } else if c == '{' {
+                        in_comment = true;
+                    } else if c == ';' {
+                        break;
+                    }
+                }
+                if self.headers_only {
+                    continue;
+                }
+            }
+            chunk.push_str(&text);
+            chunk.push('\n');
+        }
+
+        if first_line == 0 {
+            return Ok(None);
+        }
+        Ok(Some((first_line, chunk)))
+    }
+}
+
+impl<R: BufRead> Iterator for PgnReader<R> {
+    type Item = Result<Game, PgnError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.done {
+            let (first_line, chunk) = match self.next_chunk() {
+                Ok(Some(chunk)) => chunk,
+                Ok(None) => return None,
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(e));
+                },
+            };
+            let mut parser = Parser::new(&chunk, first_line);
+            if self.headers_only {
+                return Some(parser.parse_tags().map(|tags| Game { tags, ..Game::default() }));
+            }
+            match parser.next_game() {
+                Ok(Some(game)) => return Some(Ok(game)),
+                // Only escape lines, nothing to return
+                Ok(None) => continue,
+                Err(e) => return Some(Err(e)),
+            }
+        }
+        None
+    }
+}
+
+pub struct Parser<'a> {
+    chars: Peekable<Chars<'a>>,
+    line: usize,
+    column: usize,
+}
+
+impl<'a> Parser<'a> {
+    // first_line is the line number of the start of text in the whole file
+    pub fn new(text: &'a str, first_line: usize) -> Self {
+        Parser { chars: text.chars().peekable(), line: first_line, column: 1 }
+    }
+
+    fn error(&self, line: usize, column: usize, message: String) -> PgnError {
+        PgnError { line, column, message }
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        self.chars.peek().copied()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.chars.next()?;
+        if c == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+        Some(c)
+    }
+
+    fn skip_line(&mut self) -> String {
+        let mut text = String::new();
+        while let Some(c) = self.peek() {
+            if c == '\n' {
+                break;
+            }
+            text.push(c);
+            self.bump();
+        }
+        text
+    }
+
+    // Skip whitespace and "%" escape lines
+    fn skip_whitespace(&mut self) {
+        while let Some(c) = self.peek() {
+            if c == '%' && self.column == 1 {
+                self.skip_line();
+            } else if c.is_whitespace() || c == '\u{feff}' {
+                self.bump();
+            } else {
+                break;
+            }
+        }
+    }
+
+    fn read_symbol(&mut self) -> String {
+        let mut symbol = String::new();
+        while let Some(c) = self.peek() {
+            if c.is_ascii_alphanumeric() || "_+#=:-/".contains(c) {
+                symbol.push(c);
+                self.bump();
+            } else {
+                break;
+            }
+        }
+        symbol
+    }
+
+    pub fn next_game(&mut self) -> Result<Option<Game>, PgnError> {
+        self.skip_whitespace();
+        if self.peek().is_none() {
+            return Ok(None);
+        }
+        let mut game = Game { tags: self.parse_tags()?, ..Game::default() };
+        let position = match game.tag("FEN") {
+            Some(fen) => match Position::from_fen(fen) {
+                Ok(position) => position,
+                Err(e) => return Err(self.error(self.line, self.column, e.to_string())),
+            },
+            None => Position::new(),
+        };
+        let (moves, result) = self.parse_line(position, 0)?;
+        game.moves = moves;
+        game.result = result;
+        Ok(Some(game))
+    }
+
+    pub fn parse_tags(&mut self) -> Result<Vec<(String, String)>, PgnError> {
+        let mut tags = vec![];
+        loop {
+            self.skip_whitespace();
+            if self.peek() != Some('[') {
+                return Ok(tags);
+            }
+            let (line, column) = (self.line, self.column);
+            self.bump();
+            self.skip_whitespace();
+            let name = self.read_symbol();
+            if name.is_empty() {
+                return Err(self.error(self.line, self.column, "expected a tag name".to_string()));
+            }
+            self.skip_whitespace();
+            if self.bump() != Some('"') {
+                return Err(self.error(self.line, self.column, format!("expected a quoted value for tag '{}'", name)));
+            }
+            let mut value = String::new();
+            loop {
+                match self.bump() {
+                    Some('\\') => match self.bump() {
+                        Some(c) => value.push(c),
+                        None => break,
+                    },
+                    Some('"') => break,
+                    Some('\n') | None => return Err(self.error(line, column, format!("unterminated value for tag '{}'", name))),
+                    Some(c) => value.push(c),
+                }
+            }
+            self.skip_whitespace();
+            if self.bump() != Some(']') {
+                return Err(self.error(line, column, format!("unterminated tag '{}'", name)));
+            }
+            tags.push((name, value));
+        }
+    }
+
+    fn parse_comment(&mut self) -> Result<String, PgnError> {
+        let (line, column) = (self.line, self.column);
+        self.bump();
+        let mut comment = String::new();
+        loop {
+            match self.bump() {
+                Some('}') => return Ok(comment.trim().to_string()),
+                Some(c) => comment.push(c),
+                None => return Err(self.error(line, column, "unterminated comment".to_string())),
+            }
+        }
+    }
+
+    // Parse moves from position until the end of the game (depth 0) or of the variation
+    fn parse_line(&mut self, mut position: Position, depth: usize) -> Result<(Vec<MoveNode>, Option<String>), PgnError> {
+        let mut nodes: Vec<MoveNode> = vec![];
+        let mut before_last = position;
+        let mut pending_comments: Vec<String> = vec![];
+
+        loop {
+            self.skip_whitespace();
+            let (line, column) = (self.line, self.column);
+            let c = match self.peek() {
+                Some(c) => c,
+                None if depth > 0 => return Err(self.error(line, column, "unterminated variation".to_string())),
+                None => break,
+            };
+            match c {
+                '{' | ';' => {
+                    let comment = if c == '{' {
+                        self.parse_comment()?
+                    } else {
+                        self.bump();
+                        self.skip_line().trim().to_string()
+                    };
+                    match nodes.last_mut() {
+                        Some(node) => node.comments.push(comment),
+                        None => pending_comments.push(comment),
+                    }
+                },
+                '(' => {
+                    self.bump();
+                    if nodes.is_empty() {
+                        return Err(self.error(line, column, "variation before any move".to_string()));
+                    }
+                    let (variation, _) = self.parse_line(before_last, depth + 1)?;
+                    if let Some(node) = nodes.last_mut() {
+                        node.variations.push(variation);
+                    }
+                },
+                ')' => {
+                    if depth == 0 {
+                        return Err(self.error(line, column, "unexpected ')'".to_string()));
+                    }
+                    self.bump();
+                    return Ok((nodes, None));
+                },
+                '[' => {
+                    if depth > 0 {
+                        return Err(self.error(line, column, "unterminated variation".to_string()));
+                    }
+                    // Start of the next game, which has no result
+                    break;
+                },
+                '$' => {
+                    self.bump();
+                    let digits = self.read_symbol();
+                    let nag = match digits.parse() {
+                        Ok(nag) => nag,
+                        Err(_) => return Err(self.error(line, column, format!("invalid NAG '${}'", digits))),
+                    };
+                    match nodes.last_mut() {
+                        Some(node) => node.nags.push(nag),
+                        None => return Err(self.error(line, column, "NAG before any move".to_string())),
+                    }
+                },
+                '!' | '?' => {
+                    let mut suffix = String::new();
+                    while let Some(c @ ('!' | '?')) = self.peek() {
+                        suffix.push(c);
+                        self.bump();
+                    }
+                    let nag = match suffix.as_str() {
+                        "!" => 1,
+                        "?" => 2,
+                        "!!" => 3,
+                        "??" => 4,
+                        "!?" => 5,
+                        "?!" => 6,
+                        _ => return Err(self.error(line, column, format!("invalid annotation '{}'", suffix))),
+                    };
+                    match nodes.last_mut() {
+                        Some(node) => node.nags.push(nag),
+                        None => return Err(self.error(line, column, "annotation before any move".to_string())),
+                    }
+                },
+                '.' => {
+                    self.bump();
+                },
+                '*' => {
+                    self.bump();
+                    if depth > 0 {
+                        return Err(self.error(line, column, "result inside a variation".to_string()));
+                    }
+                    return Ok((nodes, Some("*".to_string())));
+                },
+                c if c.is_ascii_alphanumeric() => {
+                    let symbol = self.read_symbol();
+                    if symbol == "1-0" || symbol == "0-1" || symbol == "1/2-1/2" {
+                        if depth > 0 {
+                            return Err(self.error(line, column, "result inside a variation".to_string()));
+                        }
+                        return Ok((nodes, Some(symbol)));
+                    }
+                    if symbol.chars().all(|c| c.is_ascii_digit()) {
+                        // Move number, the dots are skipped on their own
+                        continue;
+                    }
+                    let mv = match position.parse_san(&symbol) {
+                        Some(mv) => mv,
+                        None => return Err(self.error(line, column, format!("illegal or ambiguous move '{}'", symbol))),
+                    };
+                    let starting_comment = if pending_comments.is_empty() { None } else { Some(pending_comments.join(" ")) };
+                    pending_comments.clear();
+                    nodes.push(MoveNode { mv, san: position.san(mv), nags: vec![], starting_comment, comments: vec![], variations: vec![] });
+                    before_last = position;
+                    position.make_move(mv);
+                },
+                _ => return Err(self.error(line, column, format!("unexpected character '{}'", c))),
+            }
+        }
+
+        Ok((nodes, None))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GAME: &str = r#"[Event "Casual game"]
+[White "Anderssen"]
+[Black "Kieseritzky"]
+[Result "1-0"]
+
+1. e4 e5 2. f4 {King's Gambit} exf4 3. Bc4 Qh4+ (3... Nf6 4. Nc3) 4. Kf1 $2 b5!?
+5. Bxb5 Nf6 1-0
+
+[Event "Second"]
+[FEN "4k3/8/8/8/8/8/4P3/4K3 w - - 0 1"]
+
+1. e4 Kd7 2. e5 *
+"#;
+
+    #[test]
+    fn parses_tags_moves_and_annotations() {
+        let games = parse_games(GAME).unwrap_or_else(|error| panic!("{}", error));
+        assert_eq!(games.len(), 2);
+        let game = &games[0];
+        assert_eq!(game.tag("White"), Some("Anderssen"));
+        assert_eq!(game.result.as_deref(), Some("1-0"));
+        let sans: Vec<&str> = game.moves.iter().map(|node| node.san.as_str()).collect();
+        assert_eq!(sans, ["e4", "e5", "f4", "exf4", "Bc4", "Qh4+", "Kf1", "b5", "Bxb5", "Nf6"]);
+        assert_eq!(game.moves[2].comments, ["King's Gambit"]);
+        assert_eq!(game.moves[5].variations.len(), 1);
+        assert_eq!(game.moves[5].variations[0].iter().map(|node| node.san.as_str()).collect::<Vec<_>>(), ["Nf6", "Nc3"]);
+        assert_eq!(game.moves[6].nags, [2]);
+        assert_eq!(game.moves[7].nags, [5]);
+        assert_eq!(game.positions().len(), 11);
+
+        let game = &games[1];
+        assert_eq!(game.result.as_deref(), Some("*"));
+        assert_eq!(game.positions().last().map(Position::to_fen).as_deref(), Some("8/3k4/8/4P3/8/8/8/4K3 b - - 0 2"));
+    }
+
+    fn parse_error(text: &str) -> PgnError {
+        match parse_games(text) {
+            Ok(_) => panic!("no error in {}", text),
+            Err(error) => error,
+        }
+    }
+
+    #[test]
+    fn illegal_move_is_reported_where_it_starts() {
+        let error = parse_error("[Event \"?\"]\n\n1. e4 e5\n2. Nf3 Ke7 3. Ke3 *\n");
+        assert_eq!((error.line, error.column), (4, 15));
+        assert!(error.message.contains("Ke3"), "{}", error.message);
+    }
+
+    #[test]
+    fn syntax_errors_have_their_position() {
+        let error = parse_error("[Event \"?\"]\n[White \"unterminated\n\n1. e4 *\n");
+        assert_eq!(error.line, 2);
+        let error = parse_error("1. e4 (1. d4 d5 *\n");
+        assert_eq!((error.line, error.column, error.message.as_str()), (1, 17, "result inside a variation"));
+        let error = parse_error("1. e4 {no end\n");
+        assert_eq!((error.line, error.column), (1, 7));
+    }
+
+    #[test]
+    fn game_without_movetext_does_not_swallow_the_next_one() {
+        let text = "[Event \"Tags only\"]\n[White \"A\"]\n\n[Event \"Full\"]\n[White \"B\"]\n\n1. e4 e5 1-0\n";
+        let games: Vec<Result<Game, PgnError>> = PgnReader::new(text.as_bytes()).collect();
+        assert_eq!(games.len(), 2);
+        let last = games[1].as_ref().unwrap_or_else(|error| panic!("{}", error));
+        assert_eq!((last.tag("Event"), last.tag("White"), last.moves.len()), (Some("Full"), Some("B"), 2));
+    }
+
+    #[test]
+    fn unterminated_comment_does_not_swallow_the_next_game() {
+        let text = "[Event \"Broken\"]\n\n1. e4 {never closed\n\n[Event \"Next\"]\n\n1. d4 *\n";
+        let games: Vec<Result<Game, PgnError>> = PgnReader::new(text.as_bytes()).collect();
+        assert_eq!(games.len(), 2);
+        assert!(games[0].is_err());
+        assert_eq!(games[1].as_ref().ok().and_then(|game| game.tag("Event")), Some("Next"));
+    }
+}
@@ -0,0 +1,684 @@
+use std::fmt::{self, Display};
+
+use crate::{Board, Color, Piece, PieceType, BISHOP_VECS, KING_VECS, KNIGHT_VECS, ROOK_VECS, attackers, find_king, is_attacked};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Move {
+    pub start_pos: (usize, usize),
+    pub end_pos: (usize, usize),
+    pub promotion: Option<PieceType>,
+}
+
+impl Move {
+    pub fn new(start_pos: (usize, usize), end_pos: (usize, usize)) -> Self {
+        Move { start_pos, end_pos, promotion: None }
+    }
+}
+
+// Long algebraic notation, as used by UCI: e2e4, e7e8q
+impl Display for Move {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}", square_name(self.start_pos), square_name(self.end_pos))?;
+        if let Some(promotion) = self.promotion {
+            write!(f, "{}", piece_letter(promotion).to_ascii_lowercase())?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FenError {
+    pub message: String,
+}
+
+impl Display for FenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid FEN: {}", self.message)
+    }
+}
+
+impl std::error::Error for FenError {}
+
+fn fen_error(message: &str) -> FenError {
+    FenError { message: message.to_string() }
+}
+
+pub const START_FEN: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+
+#[derive(Clone, Copy)]
+pub struct Position {
+    pub board: Board,
+    pub side_to_move: Color,
+    pub halfmove_clock: u32,
+    pub fullmove_number: u32,
+}
+
+impl Default for Position {
+    fn default() -> Self {
+        let back_rank = [PieceType::Rook, PieceType::Knight, PieceType::Bishop, PieceType::Queen, PieceType::King, PieceType::Bishop, PieceType::Knight, PieceType::Rook];
+        let mut board: Board = [[None; 8]; 8];
+        for (i, piece_type) in back_rank.into_iter().enumerate() {
+            board[i][0] = Some(Piece::new(piece_type, Color::White));
+            board[i][1] = Some(Piece::new(PieceType::Pawn, Color::White));
+            board[i][6] = Some(Piece::new(PieceType::Pawn, Color::Black));
+            board[i][7] = Some(Piece::new(piece_type, Color::Black));
+        }
+        Position { board, side_to_move: Color::White, halfmove_clock: 0, fullmove_number: 1 }
+    }
+}
+
+impl Position {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn from_fen(fen: &str) -> Result<Self, FenError> {
+        let fields: Vec<&str> = fen.split_whitespace().collect();
+        if fields.len() < 4 {
+            return Err(fen_error("expected at least 4 fields"));
+        }
+
+        let mut board: Board = [[None; 8]; 8];
+        let ranks: Vec<&str> = fields[0].split('/').collect();
+        if ranks.len() != 8 {
+            return Err(fen_error("expected 8 ranks"));
+        }
+        for (i, rank_str) in ranks.iter().enumerate() {
+            let rank = 7 - i;
+            let mut file = 0;
+            for c in rank_str.chars() {
+                if let Some(empty) = c.to_digit(10) {
+                    file += empty as usize;
+                    continue;
+                }
+                if file > 7 {
+                    return Err(fen_error("rank is too long"));
+                }
+                let piece_type = match piece_type_from_letter(c.to_ascii_uppercase()) {
+                    Some(piece_type) => piece_type,
+                    None => return Err(fen_error(&format!("unknown piece '{}'", c))),
+                };
+                let color = if c.is_ascii_uppercase() { Color::White } else { Color::Black };
+                let mut piece = Piece::new(piece_type, color);
+                // Castling rights are restored below from the castling field
+                piece.castle = piece.castle.map(|_| false);
+                if piece_type == PieceType::Pawn {
+                    if rank == 0 || rank == 7 {
+                        return Err(fen_error("pawn on the first or last rank"));
+                    }
+                    piece.jump = Some(rank == home_rank(color) + 1 || rank + 1 == home_rank(color));
+                }
+                board[file][rank] = Some(piece);
+                file += 1;
+            }
+            if file != 8 {
+                return Err(fen_error("rank does not have 8 files"));
+            }
+        }
+
+        let side_to_move = match fields[1] {
+            "w" => Color::White,
+            "b" => Color::Black,
+            _ => return Err(fen_error("side to move must be 'w' or 'b'")),
+        };
+
+        for color in [Color::White, Color::Black] {
+            match find_king(&board, color) {
+                Some(_) => (),
+                None => return Err(fen_error("missing king")),
+            }
+        }
+
+        if fields[2] != "-" {
+            for c in fields[2].chars() {
+                let (color, rook_file) = match c {
+                    'K' => (Color::White, 7),
+                    'Q' => (Color::White, 0),
+                    'k' => (Color::Black, 7),
+                    'q' => (Color::Black, 0),
+                    _ => return Err(fen_error(&format!("unknown castling right '{}'", c))),
+                };
+                let rank = home_rank(color);
+                let king_ok = matches!(board[4][rank], Some(piece) if piece.piece_type == PieceType::King && piece.color == color);
+                let rook_ok = matches!(board[rook_file][rank], Some(piece) if piece.piece_type == PieceType::Rook && piece.color == color);
+                if !king_ok || !rook_ok {
+                    return Err(fen_error(&format!("castling right '{}' without king and rook in place", c)));
+                }
+                for file in [4, rook_file] {
+                    if let Some(piece) = &mut board[file][rank] {
+                        piece.castle = Some(true);
+                    }
+                }
+            }
+        }
+
+        if fields[3] != "-" {
+            let ep_pos = match parse_square(fields[3]) {
+                Some(pos) => pos,
+                None => return Err(fen_error("invalid en passant square")),
+            };
+            // The pawn that just jumped stands in front of the en passant square
+            let pawn_rank = match side_to_move {
+                Color::White if ep_pos.1 == 5 => 4,
+                Color::Black if ep_pos.1 == 2 => 3,
+                _ => return Err(fen_error("en passant square on the wrong rank")),
+            };
+            match &mut board[ep_pos.0][pawn_rank] {
+                Some(piece) if piece.piece_type == PieceType::Pawn && piece.color != side_to_move => piece.en_passant = Some(true),
+                _ => return Err(fen_error("no pawn in front of the en passant square")),
+            }
+        }
+
+        let halfmove_clock = match fields.get(4) {
+            Some(field) => field.parse().map_err(|_| fen_error("invalid halfmove clock"))?,
+            None => 0,
+        };
+        let fullmove_number = match fields.get(5) {
+            Some(field) => field.parse().map_err(|_| fen_error("invalid fullmove number"))?,
+            None => 1,
+        };
+
+        let position = Position { board, side_to_move, halfmove_clock, fullmove_number };
+        if let Some(king_pos) = find_king(&position.board, side_to_move.opponent()) {
+            if is_attacked(&position.board, king_pos, side_to_move) {
+                return Err(fen_error("the side not to move is in check"));
+            }
+        }
+        Ok(position)
+    }
+
+    pub fn to_fen(&self) -> String {
+        let mut fen = String::new();
+        for rank in (0..8).rev() {
+            let mut empty = 0;
+            for file in 0..8 {
+                match self.board[file][rank] {
+                    Some(piece) => {
+                        if empty > 0 {
+                            fen.push_str(&empty.to_string());
+                            empty = 0;
+                        }
+                        let letter = piece_letter(piece.piece_type);
+                        fen.push(match piece.color {
+                            Color::White => letter,
+                            Color::Black => letter.to_ascii_lowercase(),
+                        });
+                    },
+                    None => empty += 1,
+                }
+            }
+            if empty > 0 {
+                fen.push_str(&empty.to_string());
+            }
+            if rank > 0 {
+                fen.push('/');
+            }
+        }
+
+        fen.push_str(match self.side_to_move {
+            Color::White => " w ",
+            Color::Black => " b ",
+        });
+
+        let mut castling = String::new();
+        for (color, rook_file, letter) in [(Color::White, 7, 'K'), (Color::White, 0, 'Q'), (Color::Black, 7, 'k'), (Color::Black, 0, 'q')] {
+            if self.can_castle(color, rook_file) {
+                castling.push(letter);
+            }
+        }
+        if castling.is_empty() {
+            castling.push('-');
+        }
+        fen.push_str(&castling);
+
+        fen.push(' ');
+        match self.en_passant_square() {
+            Some(pos) => fen.push_str(&square_name(pos)),
+            None => fen.push('-'),
+        }
+
+        fen.push_str(&format!(" {} {}", self.halfmove_clock, self.fullmove_number));
+        fen
+    }
+
+    // True while neither the king nor the rook on rook_file have moved
+    pub fn can_castle(&self, color: Color, rook_file: usize) -> bool {
+        let rank = home_rank(color);
+        let unmoved = |file: usize, piece_type: PieceType| matches!(self.board[file][rank], Some(piece) if piece.piece_type == piece_type && piece.color == color && piece.castle == Some(true));
+        unmoved(4, PieceType::King) && unmoved(rook_file, PieceType::Rook)
+    }
+
+    // The square behind a pawn that jumped on the last move
+    pub fn en_passant_square(&self) -> Option<(usize, usize)> {
+        let (pawn_rank, ep_rank) = match self.side_to_move {
+            Color::White => (4, 5),
+            Color::Black => (3, 2),
+        };
+        (0..8).find(|&file| matches!(self.board[file][pawn_rank], Some(piece) if piece.piece_type == PieceType::Pawn && piece.color != self.side_to_move && piece.en_passant == Some(true)))
+            .map(|file| (file, ep_rank))
+    }
+
+    pub fn piece_at(&self, pos: (usize, usize)) -> Option<Piece> {
+        self.board[pos.0][pos.1]
+    }
+
+    pub fn king_pos(&self, color: Color) -> Option<(usize, usize)> {
+        find_king(&self.board, color)
+    }
+
+    // Pieces of the given color that could take on pos
+    pub fn attackers(&self, pos: (usize, usize), color: Color) -> Vec<(usize, usize)> {
+        attackers(&self.board, pos, color)
+    }
+
+    pub fn in_check(&self) -> bool {
+        match self.king_pos(self.side_to_move) {
+            Some(king_pos) => is_attacked(&self.board, king_pos, self.side_to_move.opponent()),
+            None => false,
+        }
+    }
+
+    pub fn legal_moves(&self) -> Vec<Move> {
+        let mut moves = vec![];
+        for (i, file) in self.board.iter().enumerate() {
+            for (j, square) in file.iter().enumerate() {
+                let piece = match square {
+                    Some(piece) if piece.color == self.side_to_move => *piece,
+                    _ => continue,
+                };
+                for end_pos in self.candidate_targets(piece, (i, j)) {
+                    if !(piece.check_move)(&self.board, (i, j), end_pos) {
+                        continue;
+                    }
+                    if piece.piece_type == PieceType::Pawn && (end_pos.1 == 0 || end_pos.1 == 7) {
+                        for promotion in [PieceType::Queen, PieceType::Rook, PieceType::Bishop, PieceType::Knight] {
+                            moves.push(Move { start_pos: (i, j), end_pos, promotion: Some(promotion) });
+                        }
+                    } else {
+                        moves.push(Move::new((i, j), end_pos));
+                    }
+                }
+            }
+        }
+        moves
+    }
+
+    // Squares a piece could reach on an empty board, cut at the first blocker
+    fn candidate_targets(&self, piece: Piece, pos: (usize, usize)) -> Vec<(usize, usize)> {
+        let mut targets = vec![];
+        let mut push = |vec: (isize, isize)| -> bool {
+            let target = (pos.0 as isize + vec.0, pos.1 as isize + vec.1);
+            if !(0..8).contains(&target.0) || !(0..8).contains(&target.1) {
+                return false;
+            }
+            let target = (target.0 as usize, target.1 as usize);
+            targets.push(target);
+            self.board[target.0][target.1].is_none()
+        };
+        match piece.piece_type {
+            PieceType::Pawn => {
+                let dir = match piece.color {
+                    Color::White => 1,
+                    Color::Black => -1,
+                };
+                for vec in [(0, dir), (0, 2 * dir), (-1, dir), (1, dir)] {
+                    push(vec);
+                }
+            },
+            PieceType::Knight => {
+                for vec in KNIGHT_VECS {
+                    push(vec);
+                }
+            },
+            PieceType::King => {
+                for vec in KING_VECS.into_iter().chain([(2, 0), (-2, 0)]) {
+                    push(vec);
+                }
+            },
+            PieceType::Rook | PieceType::Bishop | PieceType::Queen => {
+                let vecs: Vec<(isize, isize)> = match piece.piece_type {
+                    PieceType::Rook => ROOK_VECS.to_vec(),
+                    PieceType::Bishop => BISHOP_VECS.to_vec(),
+                    _ => ROOK_VECS.into_iter().chain(BISHOP_VECS).collect(),
+                };
+                for vec in vecs {
+                    let mut distance = 1;
+                    while push((vec.0 * distance, vec.1 * distance)) {
+                        distance += 1;
+                    }
+                }
+            },
+        }
+        targets
+    }
+
+    // Same as looking the move up in legal_moves, without generating them
+    pub fn is_legal(&self, mv: Move) -> bool {
+        if mv.start_pos.0 > 7 || mv.start_pos.1 > 7 || mv.end_pos.0 > 7 || mv.end_pos.1 > 7 {
+            return false;
+        }
+        let piece = match self.piece_at(mv.start_pos) {
+            Some(piece) if piece.color == self.side_to_move => piece,
+            _ => return false,
+        };
+        let promotes = piece.piece_type == PieceType::Pawn && (mv.end_pos.1 == 0 || mv.end_pos.1 == 7);
+        let promotion_ok = match mv.promotion {
+            None => !promotes,
+            Some(promotion) => promotes && !matches!(promotion, PieceType::Pawn | PieceType::King),
+        };
+        promotion_ok && (piece.check_move)(&self.board, mv.start_pos, mv.end_pos)
+    }
+
+    pub fn is_capture(&self, mv: Move) -> bool {
+        match self.piece_at(mv.end_pos) {
+            Some(_) => true,
+            // En passant
+            None => matches!(self.piece_at(mv.start_pos), Some(piece) if piece.piece_type == PieceType::Pawn && mv.start_pos.0 != mv.end_pos.0),
+        }
+    }
+
+    pub fn is_checkmate(&self) -> bool {
+        self.in_check() && self.legal_moves().is_empty()
+    }
+
+    pub fn is_stalemate(&self) -> bool {
+        !self.in_check() && self.legal_moves().is_empty()
+    }
+
+    // The move has to be legal, see legal_moves
+    pub fn make_move(&mut self, mv: Move) {
+        let piece = match self.piece_at(mv.start_pos) {
+            Some(piece) => piece,
+            None => return,
+        };
+        let resets_clock = piece.piece_type == PieceType::Pawn || self.is_capture(mv);
+
+        (piece.exec_move)(&mut self.board, mv.start_pos, mv.end_pos);
+        if let Some(promotion) = mv.promotion {
+            let mut promoted = Piece::new(promotion, piece.color);
+            promoted.castle = promoted.castle.map(|_| false);
+            self.board[mv.end_pos.0][mv.end_pos.1] = Some(promoted);
+        }
+
+        // En passant is only possible right after the jump
+        let opponent = piece.color.opponent();
+        for file in self.board.iter_mut() {
+            for other in file.iter_mut().flatten() {
+                if other.piece_type == PieceType::Pawn && other.color == opponent {
+                    other.en_passant = Some(false);
+                }
+            }
+        }
+
+        self.halfmove_clock = if resets_clock { 0 } else { self.halfmove_clock + 1 };
+        if self.side_to_move == Color::Black {
+            self.fullmove_number += 1;
+        }
+        self.side_to_move = opponent;
+    }
+
+    // Pass the turn, for the null move search
+    pub fn make_null_move(&mut self) {
+        let opponent = self.side_to_move.opponent();
+        for file in self.board.iter_mut() {
+            for other in file.iter_mut().flatten() {
+                if other.piece_type == PieceType::Pawn && other.color == opponent {
+                    other.en_passant = Some(false);
+                }
+            }
+        }
+        self.halfmove_clock += 1;
+        if self.side_to_move == Color::Black {
+            self.fullmove_number += 1;
+        }
+        self.side_to_move = opponent;
+    }
+
+    // Standard algebraic notation of a legal move
+    pub fn san(&self, mv: Move) -> String {
+        let piece = match self.piece_at(mv.start_pos) {
+            Some(piece) => piece,
+            None => return mv.to_string(),
+        };
+        let mut san = String::new();
+
+        if piece.piece_type == PieceType::King && mv.start_pos.0.abs_diff(mv.end_pos.0) == 2 {
+            san.push_str(if mv.end_pos.0 > mv.start_pos.0 { "O-O" } else { "O-O-O" });
+        } else if piece.piece_type == PieceType::Pawn {
+            if self.is_capture(mv) {
+                san.push(file_letter(mv.start_pos.0));
+                san.push('x');
+            }
+            san.push_str(&square_name(mv.end_pos));
+            if let Some(promotion) = mv.promotion {
+                san.push('=');
+                san.push(piece_letter(promotion));
+            }
+        } else {
+            san.push(piece_letter(piece.piece_type));
+            let others: Vec<Move> = self.legal_moves().into_iter().filter(|other| {
+                other.end_pos == mv.end_pos && other.start_pos != mv.start_pos
+                    && matches!(self.piece_at(other.start_pos), Some(other_piece) if other_piece.piece_type == piece.piece_type)
+            }).collect();
+            if !others.is_empty() {
+                if others.iter().all(|other| other.start_pos.0 != mv.start_pos.0) {
+                    san.push(file_letter(mv.start_pos.0));
+                } else if others.iter().all(|other| other.start_pos.1 != mv.start_pos.1) {
+                    san.push(rank_digit(mv.start_pos.1));
+                } else {
+                    san.push_str(&square_name(mv.start_pos));
+                }
+            }
+            if self.is_capture(mv) {
+                san.push('x');
+            }
+            san.push_str(&square_name(mv.end_pos));
+        }
+
+        let mut next = *self;
+        next.make_move(mv);
+        if next.in_check() {
+            san.push(if next.legal_moves().is_empty() { '#' } else { '+' });
+        }
+        san
+    }
+
+    // A legal move in long algebraic notation, as UCI sends them: e2e4, e7e8q
+    pub fn parse_uci(&self, text: &str) -> Option<Move> {
+        self.legal_moves().into_iter().find(|mv| mv.to_string() == text)
+    }
+
+    // Return None when the move is illegal or ambiguous
+    pub fn parse_san(&self, san: &str) -> Option<Move> {
+        let san = san.trim_end_matches(['+', '#', '!', '?']);
+        let legal_moves = self.legal_moves();
+
+        if san == "O-O" || san == "0-0" || san == "O-O-O" || san == "0-0-0" {
+            let king_pos = self.king_pos(self.side_to_move)?;
+            let end_file = if san.len() == 3 { king_pos.0 + 2 } else { king_pos.0.checked_sub(2)? };
+            let mv = Move::new(king_pos, (end_file, king_pos.1));
+            return if legal_moves.contains(&mv) && matches!(self.piece_at(king_pos), Some(piece) if piece.piece_type == PieceType::King) { Some(mv) } else { None };
+        }
+
+        let mut chars: Vec<char> = san.chars().collect();
+        let piece_type = match chars.first() {
+            Some(&c) if c.is_ascii_uppercase() => {
+                chars.remove(0);
+                piece_type_from_letter(c)?
+            },
+            _ => PieceType::Pawn,
+        };
+
+        let mut promotion = None;
+        if piece_type == PieceType::Pawn {
+            if let Some(&c) = chars.last() {
+                if c.is_ascii_alphabetic() && !('a'..='h').contains(&c) {
+                    promotion = Some(piece_type_from_letter(c.to_ascii_uppercase())?);
+                    chars.pop();
+                    if chars.last() == Some(&'=') {
+                        chars.pop();
+                    }
+                }
+            }
+        }
+
+        if chars.len() < 2 {
+            return None;
+        }
+        let target_str: String = chars[chars.len() - 2..].iter().collect();
+        let end_pos = parse_square(&target_str)?;
+        let mut from_file = None;
+        let mut from_rank = None;
+        for &c in &chars[..chars.len() - 2] {
+            match c {
+                'a'..='h' => from_file = Some(c as usize - 'a' as usize),
+                '1'..='8' => from_rank = Some(c as usize - '1' as usize),
+                'x' | '-' | ':' => (),
+                _ => return None,
+            }
+        }
+
+        let mut candidates = legal_moves.into_iter().filter(|mv| {
+            mv.end_pos == end_pos
+                && mv.promotion == promotion
+                && from_file.is_none_or(|file| mv.start_pos.0 == file)
+                && from_rank.is_none_or(|rank| mv.start_pos.1 == rank)
+                && matches!(self.piece_at(mv.start_pos), Some(piece) if piece.piece_type == piece_type)
+        });
+        let mv = candidates.next()?;
+        match candidates.next() {
+            Some(_) => None,
+            None => Some(mv),
+        }
+    }
+}
+
+pub fn home_rank(color: Color) -> usize {
+    match color {
+        Color::White => 0,
+        Color::Black => 7,
+    }
+}
+
+pub fn file_letter(file: usize) -> char {
+    (b'a' + file as u8) as char
+}
+
+pub fn rank_digit(rank: usize) -> char {
+    (b'1' + rank as u8) as char
+}
+
+pub fn square_name(pos: (usize, usize)) -> String {
+    format!("{}{}", file_letter(pos.0), rank_digit(pos.1))
+}
+
+pub fn parse_square(name: &str) -> Option<(usize, usize)> {
+    let chars: Vec<char> = name.chars().collect();
+    if chars.len() != 2 || !('a'..='h').contains(&chars[0]) || !('1'..='8').contains(&chars[1]) {
+        return None;
+    }
+    Some((chars[0] as usize - 'a' as usize, chars[1] as usize - '1' as usize))
+}
+
+pub fn piece_letter(piece_type: PieceType) -> char {
+    match piece_type {
+        PieceType::Pawn => 'P',
+        PieceType::Rook => 'R',
+        PieceType::Knight => 'N',
+        PieceType::Bishop => 'B',
+        PieceType::Queen => 'Q',
+        PieceType::King => 'K',
+    }
+}
+
+pub fn piece_type_from_letter(letter: char) -> Option<PieceType> {
+    match letter {
+        'P' => Some(PieceType::Pawn),
+        'R' => Some(PieceType::Rook),
+        'N' => Some(PieceType::Knight),
+        'B' => Some(PieceType::Bishop),
+        'Q' => Some(PieceType::Queen),
+        'K' => Some(PieceType::King),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn perft(position: &Position, depth: u32) -> u64 {
+        if depth == 0 {
+            return 1;
+        }
+        let moves = position.legal_moves();
+        if depth == 1 {
+            return moves.len() as u64;
+        }
+        moves.into_iter().map(|mv| {
+            let mut next = *position;
+            next.make_move(mv);
+            perft(&next, depth - 1)
+        }).sum()
+    }
+
+    fn perft_fen(fen: &str, depth: u32) -> u64 {
+        let position = Position::from_fen(fen).unwrap_or_else(|error| panic!("{}: {}", fen, error));
+        perft(&position, depth)
+    }
+
+    #[test]
+    fn perft_start_position() {
+        assert_eq!(perft_fen(START_FEN, 1), 20);
+        assert_eq!(perft_fen(START_FEN, 2), 400);
+        assert_eq!(perft_fen(START_FEN, 3), 8902);
+    }
+
+    // Castling through and out of check, en passant, promotions
+    #[test]
+    fn perft_kiwipete() {
+        let kiwipete = "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1";
+        assert_eq!(perft_fen(kiwipete, 1), 48);
+        assert_eq!(perft_fen(kiwipete, 2), 2039);
+        assert_eq!(perft_fen(kiwipete, 3), 97862);
+    }
+
+    // En passant captures that would leave the king in check
+    #[test]
+    fn perft_en_passant_pins() {
+        assert_eq!(perft_fen("8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1", 4), 43238);
+    }
+
+    #[test]
+    fn perft_promotions_and_castling() {
+        assert_eq!(perft_fen("r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1", 3), 9467);
+    }
+
+    #[test]
+    fn san_round_trip() {
+        let position = Position::from_fen("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1").unwrap_or_default();
+        for mv in position.legal_moves() {
+            let san = position.san(mv);
+            assert_eq!(position.parse_san(&san), Some(mv), "{}", san);
+        }
+        let san = |text: &str| position.parse_san(text).map(|mv| position.san(mv));
+        assert_eq!(san("O-O"), Some("O-O".to_string()));
+        assert_eq!(san("Nxf7"), Some("Nxf7".to_string()));
+        assert_eq!(san("Qxf6"), Some("Qxf6".to_string()));
+        assert_eq!(san("Ke2"), None);
+
+        // Both knights can go to d2: the file is needed
+        let position = Position::from_fen("4k3/8/8/8/8/8/8/1N2KN2 w - - 0 1").unwrap_or_default();
+        let san = |text: &str| position.parse_san(text).map(|mv| position.san(mv));
+        assert_eq!(san("Nd2"), None);
+        assert_eq!(san("Nbd2"), Some("Nbd2".to_string()));
+        assert_eq!(san("Nfd2"), Some("Nfd2".to_string()));
+    }
+
+    #[test]
+    fn fen_round_trip() {
+        for fen in [START_FEN, "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1", "rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3"] {
+            assert_eq!(Position::from_fen(fen).map(|position| position.to_fen()).ok(), Some(fen.to_string()));
+        }
+    }
+}
@@ -0,0 +1,344 @@
+use std::collections::HashMap;
+use std::fmt::{self, Display};
+
+use crate::polyglot::polyglot_key;
+use crate::position::{Move, Position};
+
+// What the side to move has to achieve, within a number of moves
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Stipulation {
+    // Mate against any defence
+    Mate,
+    // Both sides play together so that the side to move gets mated, it moves first
+    Helpmate,
+    // Force the opponent to give mate, the opponent trying not to
+    Selfmate,
+    // A selfmate where both sides have to give mate when they can
+    Reflexmate,
+}
+
+impl Stipulation {
+    pub fn prefix(self) -> &'static str {
+        match self {
+            Stipulation::Mate => "#",
+            Stipulation::Helpmate => "h#",
+            Stipulation::Selfmate => "s#",
+            Stipulation::Reflexmate => "r#",
+        }
+    }
+
+    // Stipulation and number of moves written as in problem collections: #2, h#3, s#2, r#1
+    pub fn parse(text: &str) -> Option<(Stipulation, u32)> {
+        let (stipulation, moves) = match text.split_once('#')? {
+            ("", moves) => (Stipulation::Mate, moves),
+            ("h", moves) => (Stipulation::Helpmate, moves),
+            ("s", moves) => (Stipulation::Selfmate, moves),
+            ("r", moves) => (Stipulation::Reflexmate, moves),
+            _ => return None,
+        };
+        let moves = moves.parse().ok().filter(|&moves| moves > 0)?;
+        Some((stipulation, moves))
+    }
+}
+
+// A move of the side to move that works, with every reply of the opponent
+#[derive(Debug, Clone, PartialEq)]
+pub struct Variation {
+    pub mv: Move,
+    pub replies: Vec<Reply>,
+}
+
+// A reply, and the moves that still work after it. More than one of them is a dual,
+// none means the reply ended the problem.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Reply {
+    pub mv: Move,
+    pub continuations: Vec<Variation>,
+}
+
+#[derive(Clone)]
+pub struct Solution {
+    pub position: Position,
+    pub stipulation: Stipulation,
+    pub moves: u32,
+    // Key moves with their full tree, for direct mates, selfmates and reflexmates
+    pub keys: Vec<Variation>,
+    // Every line from the start to the mate, for helpmates
+    pub lines: Vec<Vec<Move>>,
+}
+
+impl Solution {
+    pub fn is_solved(&self) -> bool {
+        !self.keys.is_empty() || !self.lines.is_empty()
+    }
+
+    // More than one key, or more than one helpmate solution
+    pub fn is_cooked(&self) -> bool {
+        self.keys.len() > 1 || self.lines.len() > 1
+    }
+
+    // Replies after which more than one move works, in all the key trees
+    pub fn duals(&self) -> usize {
+        fn count(variation: &Variation) -> usize {
+            variation.replies.iter().map(|reply| (reply.continuations.len() > 1) as usize + reply.continuations.iter().map(count).sum::<usize>()).sum()
+        }
+        self.keys.iter().map(count).sum()
+    }
+}
+
+// Move number and dots before a move, for the side that started and the one replying
+fn numbered(number: u32, first: bool) -> String {
+    if first { format!("{}.", number) } else { format!("{}...", number) }
+}
+
+fn write_variation(f: &mut fmt::Formatter<'_>, position: &Position, variation: &Variation, number: u32, indent: usize) -> fmt::Result {
+    let key = if number == 1 { "!" } else { "" };
+    writeln!(f, "{:indent$}{}{}{}", "", numbered(number, true), position.san(variation.mv), key, indent = indent)?;
+    let mut next = *position;
+    next.make_move(variation.mv);
+    for reply in &variation.replies {
+        let dual = if reply.continuations.len() > 1 { " (dual)" } else { "" };
+        writeln!(f, "{:indent$}{}{}{}", "", numbered(number, false), next.san(reply.mv), dual, indent = indent + 2)?;
+        let mut after = next;
+        after.make_move(reply.mv);
+        for continuation in &reply.continuations {
+            write_variation(f, &after, continuation, number + 1, indent + 4)?;
+        }
+    }
+    Ok(())
+}
+
+// Keys and their trees in problem notation: 1.Ra6! then 1...bxa6 and 2.b7#, helpmates
+// as one line per solution: 1.Kb7 Ra8+ 2.Kxa8 b7#
+impl Display for Solution {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}{}", self.stipulation.prefix(), self.moves)?;
+        if !self.is_solved() {
+            return write!(f, "No solution");
+        }
+        for key in &self.keys {
+            write_variation(f, &self.position, key, 1, 0)?;
+        }
+        for line in &self.lines {
+            let mut position = self.position;
+            let mut text = vec![];
+            for (i, &mv) in line.iter().enumerate() {
+                let san = position.san(mv);
+                text.push(if i % 2 == 0 { format!("{}{}", numbered(i as u32 / 2 + 1, true), san) } else { san });
+                position.make_move(mv);
+            }
+            writeln!(f, "{}", text.join(" "))?;
+        }
+        if self.is_cooked() {
+            writeln!(f, "Cooked: {} solutions", self.keys.len().max(self.lines.len()))?;
+        }
+        write!(f, "{} duals", self.duals())
+    }
+}
+
+fn after(position: &Position, mv: Move) -> Position {
+    let mut next = *position;
+    next.make_move(mv);
+    next
+}
+
+// Proves the stipulation with nothing but the move generator: every move of the
+// side to move is tried, and every reply to it
+struct ProblemSearch {
+    stipulation: Stipulation,
+    // Positions already proven or refuted, with the number of moves left
+    known: HashMap<(u64, u32), bool>,
+}
+
+impl ProblemSearch {
+    fn new(stipulation: Stipulation) -> Self {
+        ProblemSearch { stipulation, known: HashMap::new() }
+    }
+
+    fn can_mate_in_one(position: &Position) -> bool {
+        position.legal_moves().into_iter().any(|mv| after(position, mv).is_checkmate())
+    }
+
+    // The side to move achieves the stipulation in at most n moves whatever the replies
+    fn forces(&mut self, position: &Position, n: u32) -> bool {
+        let key = (polyglot_key(position), n);
+        if let Some(&known) = self.known.get(&key) {
+            return known;
+        }
+        // In a reflexmate a mate in one has to be played, which ends the problem unsolved
+        let works = !(self.stipulation == Stipulation::Reflexmate && Self::can_mate_in_one(position))
+            && position.legal_moves().into_iter().any(|mv| self.move_works(position, mv, n));
+        self.known.insert(key, works);
+        works
+    }
+
+    // The replies the opponent can choose from, a reflexmate forces it to mate when it can
+    fn replies(&self, position: &Position) -> Vec<Move> {
+        let moves = position.legal_moves();
+        if self.stipulation == Stipulation::Reflexmate {
+            let mates: Vec<Move> = moves.iter().copied().filter(|&mv| after(position, mv).is_checkmate()).collect();
+            if !mates.is_empty() {
+                return mates;
+            }
+        }
+        moves
+    }
+
+    // The reply ended the problem the way the stipulation wants
+    fn reply_mates(&self, position: &Position) -> bool {
+        self.stipulation != Stipulation::Mate && position.is_checkmate()
+    }
+
+    fn move_works(&mut self, position: &Position, mv: Move, n: u32) -> bool {
+        let next = after(position, mv);
+        // Only a check can mate on the last move
+        if self.stipulation == Stipulation::Mate && n == 1 && !next.in_check() {
+            return false;
+        }
+        let replies = self.replies(&next);
+        if replies.is_empty() {
+            return self.stipulation == Stipulation::Mate && next.in_check();
+        }
+        replies.into_iter().all(|reply| {
+            let after = after(&next, reply);
+            self.reply_mates(&after) || (n > 1 && self.forces(&after, n - 1))
+        })
+    }
+
+    // Every move that works and what follows each reply, for the duals
+    fn variations(&mut self, position: &Position, n: u32) -> Vec<Variation> {
+        let moves: Vec<Move> = position.legal_moves().into_iter().filter(|&mv| self.move_works(position, mv, n)).collect();
+        moves.into_iter().map(|mv| {
+            let next = after(position, mv);
+            let replies = self.replies(&next).into_iter().map(|reply| {
+                let after = after(&next, reply);
+                let continuations = if self.reply_mates(&after) || n == 1 { vec![] } else { self.variations(&after, n - 1) };
+                Reply { mv: reply, continuations }
+            }).collect();
+            Variation { mv, replies }
+        }).collect()
+    }
+
+    // There is a helpmate in n moves, the side to move moving first and getting mated
+    fn helps(&mut self, position: &Position, n: u32) -> bool {
+        let key = (polyglot_key(position), n);
+        if let Some(&known) = self.known.get(&key) {
+            return known;
+        }
+        let works = position.legal_moves().into_iter().any(|first| {
+            let next = after(position, first);
+            next.legal_moves().into_iter().any(|second| {
+                let after = after(&next, second);
+                if n == 1 { after.in_check() && after.is_checkmate() } else { self.helps(&after, n - 1) }
+            })
+        });
+        self.known.insert(key, works);
+        works
+    }
+
+    fn help_lines(&mut self, position: &Position, n: u32, line: &mut Vec<Move>, lines: &mut Vec<Vec<Move>>) {
+        if !self.helps(position, n) {
+            return;
+        }
+        for first in position.legal_moves() {
+            let next = after(position, first);
+            for second in next.legal_moves() {
+                let after = after(&next, second);
+                line.extend([first, second]);
+                if n == 1 {
+                    if after.in_check() && after.is_checkmate() {
+                        lines.push(line.clone());
+                    }
+                } else {
+                    self.help_lines(&after, n - 1, line, lines);
+                }
+                line.truncate(line.len() - 2);
+            }
+        }
+    }
+}
+
+// All the solutions of the problem, with its cooks and duals
+pub fn solve(position: &Position, stipulation: Stipulation, moves: u32) -> Solution {
+    let mut solution = Solution { position: *position, stipulation, moves, keys: vec![], lines: vec![] };
+    let mut search = ProblemSearch::new(stipulation);
+    match stipulation {
+        Stipulation::Helpmate => search.help_lines(position, moves, &mut vec![], &mut solution.lines),
+        Stipulation::Reflexmate if ProblemSearch::can_mate_in_one(position) => (),
+        _ => solution.keys = search.variations(position, moves),
+    }
+    solution
+}
+
+// Every key move that mates in at most n moves against any defence, none when there is no such mate
+pub fn solve_mate(position: &Position, n: u32) -> Vec<Move> {
+    if n == 0 {
+        return vec![];
+    }
+    let mut search = ProblemSearch::new(Stipulation::Mate);
+    position.legal_moves().into_iter().filter(|&mv| search.move_works(position, mv, n)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn position(fen: &str) -> Position {
+        Position::from_fen(fen).unwrap_or_else(|error| panic!("{}: {}", fen, error))
+    }
+
+    fn keys(fen: &str, n: u32) -> Vec<String> {
+        let position = position(fen);
+        solve_mate(&position, n).into_iter().map(|mv| position.san(mv)).collect()
+    }
+
+    // The moves of a line in SAN, separated by spaces
+    fn san_line(mut position: Position, line: &[Move]) -> String {
+        let mut sans = vec![];
+        for &mv in line {
+            sans.push(position.san(mv));
+            position.make_move(mv);
+        }
+        sans.join(" ")
+    }
+
+    // Morphy: 1. Ra6 bxa6 2. b7#
+    #[test]
+    fn finds_the_key_of_a_mate_in_two() {
+        let fen = "kbK5/pp6/1P6/8/8/8/8/R7 w - - 0 1";
+        assert_eq!(keys(fen, 2), ["Ra6"]);
+        assert!(keys(fen, 1).is_empty());
+    }
+
+    #[test]
+    fn solves_a_mate_in_two_with_its_tree() {
+        let solution = solve(&position("8/8/8/8/8/8/k1K5/2R5 w - - 0 1"), Stipulation::Mate, 2);
+        assert!(solution.is_solved() && !solution.is_cooked());
+        assert_eq!(solution.duals(), 0);
+        let key = &solution.keys[0];
+        assert_eq!(solution.position.san(key.mv), "Kc3");
+        assert_eq!(key.replies.len(), 1);
+        assert_eq!(san_line(solution.position, &[key.mv, key.replies[0].mv, key.replies[0].continuations[0].mv]), "Kc3 Ka3 Ra1#");
+    }
+
+    #[test]
+    fn finds_every_helpmate_line() {
+        let fen = "7k/8/6K1/8/8/8/8/5B1R b - - 0 1";
+        let solution = solve(&position(fen), Stipulation::Helpmate, 2);
+        let mut lines: Vec<_> = solution.lines.iter().map(|line| san_line(solution.position, line)).collect();
+        lines.sort();
+        assert_eq!(lines, ["Kg8 Bh3 Kh8 Be6#", "Kg8 Kf6 Kf8 Rh8#"]);
+        assert!(solution.keys.is_empty());
+        assert!(!solve(&position(fen), Stipulation::Helpmate, 1).is_solved());
+    }
+
+    // Both 1. Kc7 and 1. Kb6 mate next move
+    #[test]
+    fn a_second_key_is_a_cook() {
+        let solution = solve(&position("k7/8/2K5/8/8/8/8/3R4 w - - 0 1"), Stipulation::Mate, 2);
+        let mut keys: Vec<_> = solution.keys.iter().map(|key| solution.position.san(key.mv)).collect();
+        keys.sort();
+        assert_eq!(keys, ["Kb6", "Kc7"]);
+        assert!(solution.is_cooked());
+    }
+}
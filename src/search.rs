@@ -0,0 +1,964 @@
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::eval::evaluate;
+use crate::movepick::{is_tactical, mvv_lva, Heuristics, MovePicker};
+use crate::polyglot::polyglot_key;
+use crate::position::{Move, Position};
+use crate::rng::Rng;
+use crate::see::see;
+use crate::skill::Skill;
+use crate::tablebase::{Tablebase, Wdl};
+use crate::timeman::TimeManager;
+use crate::tt::{Bound, TranspositionTable};
+use crate::{Color, PieceType};
+
+pub const MATE: i32 = 32000;
+pub const INFINITY: i32 = 32001;
+pub const MAX_PLY: usize = 128;
+pub const DEFAULT_HASH_MB: usize = 16;
+// Largest hash size and thread count the protocols accept
+pub const MAX_HASH_MB: usize = 4096;
+pub const MAX_THREADS: usize = 64;
+// Tablebase wins, below the mate scores since the mate itself is further away
+pub const TB_WIN: i32 = MATE - 2 * MAX_PLY as i32;
+
+// When to stop searching, nothing set means until there is nothing left to search
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Limits {
+    pub depth: Option<u32>,
+    pub nodes: Option<u64>,
+    pub movetime: Option<Duration>,
+    // Clocks and increments of both sides, the time manager splits them between the moves left
+    pub wtime: Option<Duration>,
+    pub btime: Option<Duration>,
+    pub winc: Duration,
+    pub binc: Duration,
+    pub movestogo: Option<u32>,
+    // Searching the position after the expected reply, on the opponent's time
+    pub ponder: bool,
+    // Only a stop ends the search, even once there is nothing more to find
+    pub infinite: bool,
+    // Root moves to choose from, all of them when empty
+    pub searchmoves: Vec<Move>,
+}
+
+impl Limits {
+    pub fn depth(depth: u32) -> Self {
+        Limits { depth: Some(depth), ..Limits::default() }
+    }
+
+    pub fn movetime(movetime: Duration) -> Self {
+        Limits { movetime: Some(movetime), ..Limits::default() }
+    }
+}
+
+// Parts of the search that can be switched off, to measure what each of them brings
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SearchOptions {
+    pub null_move: bool,
+    pub late_move_reductions: bool,
+    pub futility: bool,
+    pub reverse_futility: bool,
+    pub razoring: bool,
+    pub check_extensions: bool,
+}
+
+impl Default for SearchOptions {
+    fn default() -> Self {
+        SearchOptions { null_move: true, late_move_reductions: true, futility: true, reverse_futility: true, razoring: true, check_extensions: true }
+    }
+}
+
+impl SearchOptions {
+    // Names of the options as the engine takes them
+    pub const NAMES: [&'static str; 6] = ["NullMove", "LateMoveReductions", "Futility", "ReverseFutility", "Razoring", "CheckExtensions"];
+
+    fn option_mut(&mut self, name: &str) -> Option<&mut bool> {
+        let index = Self::NAMES.iter().position(|other| other.eq_ignore_ascii_case(name))?;
+        Some(match index {
+            0 => &mut self.null_move,
+            1 => &mut self.late_move_reductions,
+            2 => &mut self.futility,
+            3 => &mut self.reverse_futility,
+            4 => &mut self.razoring,
+            _ => &mut self.check_extensions,
+        })
+    }
+
+    pub fn get(&self, name: &str) -> Option<bool> {
+        let mut options = *self;
+        options.option_mut(name).map(|enabled| *enabled)
+    }
+
+    // False when there is no option of that name
+    pub fn set(&mut self, name: &str, enabled: bool) -> bool {
+        self.option_mut(name).map(|option| *option = enabled).is_some()
+    }
+}
+
+// Counters to compare versions of the search by
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct SearchStats {
+    pub quiescence_nodes: u64,
+    pub hash_cutoffs: u64,
+    pub beta_cutoffs: u64,
+    // Cutoffs by the first move searched, all of them with a perfect move ordering
+    pub first_move_cutoffs: u64,
+}
+
+impl SearchStats {
+    pub fn add(&mut self, other: &SearchStats) {
+        self.quiescence_nodes += other.quiescence_nodes;
+        self.hash_cutoffs += other.hash_cutoffs;
+        self.beta_cutoffs += other.beta_cutoffs;
+        self.first_move_cutoffs += other.first_move_cutoffs;
+    }
+
+    pub fn first_move_rate(&self) -> f64 {
+        if self.beta_cutoffs == 0 {
+            return 0.0;
+        }
+        self.first_move_cutoffs as f64 / self.beta_cutoffs as f64
+    }
+}
+
+// One of the best moves with its score and principal variation
+#[derive(Debug, Clone, PartialEq)]
+pub struct PvLine {
+    pub mv: Move,
+    pub score: i32,
+    pub pv: Vec<Move>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SearchResult {
+    pub best_move: Option<Move>,
+    // Centipawns for the side to move, MATE - n when it mates in n plies
+    pub score: i32,
+    pub pv: Vec<Move>,
+    pub nodes: u64,
+    // Last completed iteration
+    pub depth: u32,
+    pub stats: SearchStats,
+    // The best moves, best first, as many as asked for with MultiPV
+    pub lines: Vec<PvLine>,
+}
+
+// Progress of a search, after each iteration
+#[derive(Debug, Clone, PartialEq)]
+pub struct SearchInfo {
+    pub depth: u32,
+    pub score: i32,
+    pub pv: Vec<Move>,
+    // Every line with MultiPV, the first one is the score and pv above
+    pub lines: Vec<PvLine>,
+    // Nodes of all the threads
+    pub nodes: u64,
+    pub nps: u64,
+    pub time: Duration,
+    // Permill of the transposition table in use
+    pub hashfull: usize,
+}
+
+// Called by the main thread of a search with its progress
+pub type InfoCallback = Box<dyn Fn(&SearchInfo) + Send>;
+
+// Number of moves to mate for a mate score, negative when getting mated
+pub fn mate_in(score: i32) -> Option<i32> {
+    if score >= MATE - MAX_PLY as i32 {
+        Some((MATE - score + 1) / 2)
+    } else if score <= -MATE + MAX_PLY as i32 {
+        Some(-(MATE + score) / 2)
+    } else {
+        None
+    }
+}
+
+pub fn search(position: &Position, limits: Limits) -> SearchResult {
+    Searcher::new().search(position, limits)
+}
+
+// Sent to a search from another thread: stop now, or the expected move was played
+#[derive(Debug, Default)]
+pub struct SearchSignals {
+    stop: AtomicBool,
+    ponderhit: AtomicBool,
+}
+
+impl SearchSignals {
+    pub fn stop(&self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+
+    pub fn ponderhit(&self) {
+        self.ponderhit.store(true, Ordering::Relaxed);
+    }
+
+    fn is_stopped(&self) -> bool {
+        self.stop.load(Ordering::Relaxed)
+    }
+
+    fn is_ponderhit(&self) -> bool {
+        self.ponderhit.load(Ordering::Relaxed)
+    }
+}
+
+// Searches on as many threads as asked for, sharing the transposition table. Each
+// helper thread searches on its own and leaves what it finds in the table, where
+// the main thread finds it (Lazy SMP).
+pub struct Searcher {
+    tt: TranspositionTable,
+    // What each thread learnt about move ordering, kept from one search to the next
+    heuristics: Vec<Heuristics>,
+    tablebase: Option<Arc<Tablebase>>,
+    options: SearchOptions,
+    // Number of best moves to search
+    multi_pv: usize,
+    skill: Skill,
+    rng: Rng,
+    // Signals handed out for the next search, each search starts with fresh ones otherwise
+    signals: Option<Arc<SearchSignals>>,
+    info: Option<InfoCallback>,
+    // Keys of the positions before the one searched, for repetitions
+    game_keys: Vec<u64>,
+}
+
+impl Default for Searcher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Searcher {
+    pub fn new() -> Self {
+        Searcher { tt: TranspositionTable::new(DEFAULT_HASH_MB), heuristics: vec![Heuristics::new()], options: SearchOptions::default(), multi_pv: 1, skill: Skill::default(), rng: Rng::from_time(), signals: None, info: None, tablebase: None, game_keys: vec![] }
+    }
+
+    pub fn options(&self) -> SearchOptions {
+        self.options
+    }
+
+    pub fn set_options(&mut self, options: SearchOptions) {
+        self.options = options;
+    }
+
+    pub fn multi_pv(&self) -> usize {
+        self.multi_pv
+    }
+
+    pub fn set_multi_pv(&mut self, multi_pv: usize) {
+        self.multi_pv = multi_pv.max(1);
+    }
+
+    pub fn skill(&self) -> Skill {
+        self.skill
+    }
+
+    pub fn set_skill(&mut self, skill: Skill) {
+        self.skill = skill;
+    }
+
+    // Signals for the next search, to be sent from another thread while it runs
+    pub fn signals(&mut self) -> Arc<SearchSignals> {
+        self.signals.insert(Arc::default()).clone()
+    }
+
+    // What to call with the progress of the searches, None to stop reporting it
+    pub fn set_info_callback(&mut self, info: Option<InfoCallback>) {
+        self.info = info;
+    }
+
+    pub fn threads(&self) -> usize {
+        self.heuristics.len()
+    }
+
+    pub fn set_threads(&mut self, threads: usize) {
+        self.heuristics.resize_with(threads.max(1), Heuristics::new);
+    }
+
+    pub fn set_hash_size(&mut self, megabytes: usize) {
+        self.tt.resize(megabytes);
+    }
+
+    // Syzygy tables probed once few enough pieces are left
+    pub fn set_tablebase(&mut self, tablebase: Option<Arc<Tablebase>>) {
+        self.tablebase = tablebase;
+    }
+
+    // Permill of the transposition table used by the last search
+    pub fn hashfull(&self) -> usize {
+        self.tt.hashfull()
+    }
+
+    // Forget what previous searches found, for a new game
+    pub fn clear_hash(&mut self) {
+        self.tt.clear();
+        self.heuristics.iter_mut().for_each(Heuristics::clear);
+    }
+
+    // Positions played before the one to search, oldest first, so that repeating them counts as a draw
+    pub fn set_game_history(&mut self, positions: &[Position]) {
+        self.game_keys = positions.iter().map(polyglot_key).collect();
+    }
+
+    // Iterative deepening until a limit is hit, the result is the one of the last completed depth
+    // of the main thread, with the nodes of all the threads
+    pub fn search(&mut self, position: &Position, limits: Limits) -> SearchResult {
+        // A stop sent to an earlier search must not end this one
+        let signals = self.signals.take().unwrap_or_default();
+        self.tt.new_search();
+        let root_moves = position.legal_moves().into_iter().filter(|mv| limits.searchmoves.is_empty() || limits.searchmoves.contains(mv)).count();
+        if root_moves == 0 {
+            let score = if position.in_check() { -MATE } else { 0 };
+            return SearchResult { score, ..SearchResult::default() };
+        }
+
+        let stop = AtomicBool::new(false);
+        let total_nodes = AtomicU64::new(0);
+        let limits = self.skill.limit(limits);
+        let multi_pv = self.multi_pv.max(self.skill.multi_pv()).min(root_moves);
+        let (tt, options, game_keys, signals, info, tablebase) = (&self.tt, self.options, &self.game_keys, &*signals, self.info.as_deref(), self.tablebase.as_deref());
+        let (main_heuristics, helper_heuristics) = self.heuristics.split_first_mut().unwrap_or_else(|| unreachable!());
+        // Helpers have no limits of their own, the main thread stops them
+        let helper_limits = Limits { searchmoves: limits.searchmoves.clone(), ..Limits::default() };
+        let mut result = thread::scope(|scope| {
+            let helpers: Vec<_> = helper_heuristics.iter_mut().enumerate().map(|(i, heuristics)| {
+                let (stop, total_nodes, helper_limits) = (&stop, &total_nodes, &helper_limits);
+                scope.spawn(move || {
+                    let mut worker = Worker::new(i + 1, tt, heuristics, options, game_keys, stop, total_nodes);
+                    worker.tablebase = tablebase;
+                    worker.iterate(position, helper_limits.clone())
+                })
+            }).collect();
+
+            let mut main = Worker::new(0, tt, main_heuristics, options, game_keys, &stop, &total_nodes);
+            main.multi_pv = multi_pv;
+            main.signals = Some(signals);
+            main.info = info;
+            main.tablebase = tablebase;
+            let mut result = main.iterate(position, limits);
+            stop.store(true, Ordering::Relaxed);
+            for helper in helpers {
+                if let Ok(helper) = helper.join() {
+                    result.nodes += helper.nodes;
+                    result.stats.add(&helper.stats);
+                }
+            }
+            result
+        });
+
+        // Weaker levels play one of the best moves rather than the best
+        let line = self.skill.pick(&result.lines, &mut self.rng);
+        if line > 0 {
+            let PvLine { mv, score, pv } = result.lines[line].clone();
+            result.best_move = Some(mv);
+            result.score = score;
+            result.pv = pv;
+        }
+        result
+    }
+}
+
+// A search running on its own thread, so that the caller can go on with other
+// things. It owns the searcher until the search is over.
+pub struct SearchHandle {
+    signals: Arc<SearchSignals>,
+    best_move: Arc<Mutex<Option<Move>>>,
+    thread: JoinHandle<(Searcher, SearchResult)>,
+}
+
+impl SearchHandle {
+    // Starts the search, info is called with its progress after each iteration
+    pub fn start(mut searcher: Searcher, position: Position, limits: Limits, info: impl Fn(&SearchInfo) + Send + 'static) -> Self {
+        let signals = searcher.signals();
+        let best_move = Arc::new(Mutex::new(None));
+        let shared = best_move.clone();
+        searcher.set_info_callback(Some(Box::new(move |progress: &SearchInfo| {
+            if let Ok(mut best_move) = shared.lock() {
+                *best_move = progress.pv.first().copied();
+            }
+            info(progress);
+        })));
+        let thread = thread::spawn(move || {
+            let result = searcher.search(&position, limits);
+            searcher.set_info_callback(None);
+            (searcher, result)
+        });
+        SearchHandle { signals, best_move, thread }
+    }
+
+    // To stop the search or send it a ponderhit once the handle is gone
+    pub fn signals(&self) -> Arc<SearchSignals> {
+        self.signals.clone()
+    }
+
+    // Best move of the last completed iteration, None before the first one
+    pub fn best_move(&self) -> Option<Move> {
+        self.best_move.lock().ok().and_then(|best_move| *best_move)
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.thread.is_finished()
+    }
+
+    // Ends the search as soon as possible, its result is then given by join
+    pub fn stop(&self) {
+        self.signals.stop();
+    }
+
+    pub fn ponderhit(&self) {
+        self.signals.ponderhit();
+    }
+
+    // Waits for the end of the search, and gives the searcher back with the result
+    pub fn join(self) -> (Searcher, SearchResult) {
+        match self.thread.join() {
+            Ok(done) => done,
+            Err(panic) => std::panic::resume_unwind(panic),
+        }
+    }
+
+    // Stops the search and drops its result
+    pub fn cancel(self) -> Searcher {
+        self.stop();
+        self.join().0
+    }
+}
+
+// The search of one thread
+struct Worker<'a> {
+    // 0 for the main thread, which is the one checking the limits
+    thread: usize,
+    tt: &'a TranspositionTable,
+    heuristics: &'a mut Heuristics,
+    options: SearchOptions,
+    multi_pv: usize,
+    // Root moves of the lines already found in this iteration
+    excluded: Vec<Move>,
+    // Set by the main thread when the search is over
+    stop: &'a AtomicBool,
+    // Stop and ponderhit from outside the search, for the main thread
+    signals: Option<&'a SearchSignals>,
+    info: Option<&'a (dyn Fn(&SearchInfo) + Send)>,
+    // Nodes of all the threads, updated when each thread checks its limits
+    total_nodes: &'a AtomicU64,
+    nodes: u64,
+    // Nodes of this thread already added to the total
+    reported_nodes: u64,
+    node_limit: Option<u64>,
+    // Node count at which the limits are looked at next, every 1024 nodes and at the node limit
+    next_check: u64,
+    time: Option<TimeManager>,
+    // Set when a limit is hit, the iteration in progress is then thrown away
+    stopped: bool,
+    // Principal variation found below each ply
+    pv: Vec<Vec<Move>>,
+    // Keys of the game so far then of the current line, for repetitions
+    keys: Vec<u64>,
+    // Move played at each ply of the current line
+    played: Vec<Option<Move>>,
+    stats: SearchStats,
+    // No null move before this ply, while verifying one
+    null_min_ply: usize,
+    tablebase: Option<&'a Tablebase>,
+}
+
+impl<'a> Worker<'a> {
+    fn new(thread: usize, tt: &'a TranspositionTable, heuristics: &'a mut Heuristics, options: SearchOptions, game_keys: &[u64], stop: &'a AtomicBool, total_nodes: &'a AtomicU64) -> Self {
+        heuristics.new_search();
+        Worker {
+            thread,
+            tt,
+            heuristics,
+            options,
+            multi_pv: 1,
+            excluded: vec![],
+            stop,
+            signals: None,
+            info: None,
+            total_nodes,
+            nodes: 0,
+            reported_nodes: 0,
+            node_limit: None,
+            next_check: 0,
+            time: None,
+            stopped: false,
+            pv: vec![vec![]; MAX_PLY + 1],
+            keys: game_keys.to_vec(),
+            played: vec![None; MAX_PLY + 1],
+            stats: SearchStats::default(),
+            null_min_ply: 0,
+            tablebase: None,
+        }
+    }
+
+    fn iterate(&mut self, position: &Position, limits: Limits) -> SearchResult {
+        self.node_limit = limits.nodes;
+        let mut result = SearchResult::default();
+        let max_depth = limits.depth.unwrap_or(MAX_PLY as u32).clamp(1, MAX_PLY as u32);
+        let mut time = TimeManager::new(&limits, position.side_to_move);
+        // Root moves left out when only some of them are to be searched
+        let skipped: Vec<Move> = match limits.searchmoves.is_empty() {
+            true => vec![],
+            false => position.legal_moves().into_iter().filter(|mv| !limits.searchmoves.contains(mv)).collect(),
+        };
+        for depth in 1..=max_depth {
+            // Helpers skip some depths so that they don't all search the same tree as the main thread
+            if self.thread > 0 && depth > 1 && (depth as usize + self.thread).is_multiple_of(2) {
+                continue;
+            }
+            // The first iteration always completes so that there is a move to play
+            if depth > 1 {
+                self.time = Some(time);
+            }
+            // Each line is the best move once the moves of the previous lines are left out
+            self.excluded.clone_from(&skipped);
+            let mut lines = vec![];
+            for index in 0..self.multi_pv {
+                let previous = result.lines.get(index).map_or(result.score, |line: &PvLine| line.score);
+                let score = self.aspiration_search(position, depth, previous);
+                if self.stopped {
+                    break;
+                }
+                let pv = self.pv[0].clone();
+                let mv = match pv.first() {
+                    Some(&mv) => mv,
+                    None => break,
+                };
+                self.excluded.push(mv);
+                lines.push(PvLine { mv, score, pv });
+            }
+            if self.stopped || lines.is_empty() {
+                break;
+            }
+            if let Some(current) = self.time {
+                time = current;
+            }
+            self.poll_ponderhit(&mut time);
+            lines.sort_by_key(|line| -line.score);
+            let score = lines[0].score;
+            result = SearchResult { best_move: Some(lines[0].mv), score, pv: lines[0].pv.clone(), nodes: self.nodes, depth, stats: self.stats, lines };
+            if let Some(info) = self.info {
+                let nodes = self.total_nodes.load(Ordering::Relaxed).max(self.nodes);
+                let elapsed = time.elapsed();
+                let nps = (nodes as f64 / elapsed.as_secs_f64().max(0.001)) as u64;
+                info(&SearchInfo { depth, score, pv: result.pv.clone(), lines: result.lines.clone(), nodes, nps, time: elapsed, hashfull: self.tt.hashfull() });
+            }
+
+            // No deeper search will find a shorter mate
+            let mate_found = mate_in(score).is_some_and(|moves| moves.unsigned_abs() * 2 <= depth);
+            if mate_found || self.out_of_nodes() || !time.can_start_iteration() {
+                break;
+            }
+        }
+        // A ponder search waits for the opponent's move before giving its own
+        while let Some(signals) = self.signals.filter(|_| time.is_pondering() || limits.infinite) {
+            if signals.is_stopped() {
+                break;
+            }
+            self.poll_ponderhit(&mut time);
+            thread::sleep(Duration::from_millis(1));
+        }
+        // The node limit or a stop can cut the first iteration short, the best root move so far is played
+        if result.best_move.is_none() {
+            result.best_move = self.pv[0].first().copied().or_else(|| position.legal_moves().first().copied());
+            result.pv = result.best_move.into_iter().collect();
+        }
+        result.nodes = self.nodes;
+        result.stats = self.stats;
+        result
+    }
+
+    // Search with a narrow window around the previous score, widened when the score falls outside
+    fn aspiration_search(&mut self, position: &Position, depth: u32, previous: i32) -> i32 {
+        let mut delta = 25;
+        let (mut alpha, mut beta) = if depth >= 4 && mate_in(previous).is_none() {
+            (previous - delta, previous + delta)
+        } else {
+            (-INFINITY, INFINITY)
+        };
+        loop {
+            let score = self.negamax(position, depth, alpha, beta, 0);
+            if self.stopped || (alpha < score && score < beta) {
+                return score;
+            }
+            if score <= alpha {
+                alpha = (score - delta).max(-INFINITY);
+            } else {
+                beta = (score + delta).min(INFINITY);
+            }
+            delta *= 2;
+        }
+    }
+
+    fn out_of_nodes(&self) -> bool {
+        let total_nodes = self.total_nodes.load(Ordering::Relaxed) + self.nodes - self.reported_nodes;
+        self.node_limit.is_some_and(|limit| total_nodes >= limit)
+    }
+
+    // Nodes count from the first iteration, time only once it gave a move to play
+    fn check_limits(&mut self) {
+        let total_nodes = self.total_nodes.fetch_add(self.nodes - self.reported_nodes, Ordering::Relaxed) + self.nodes - self.reported_nodes;
+        self.reported_nodes = self.nodes;
+        if self.thread > 0 {
+            self.next_check = self.nodes + 1024;
+            self.stopped = self.stop.load(Ordering::Relaxed);
+            return;
+        }
+        let nodes_left = self.node_limit.map_or(u64::MAX, |limit| limit.saturating_sub(total_nodes));
+        self.next_check = self.nodes + nodes_left.min(1024);
+        // A stop or the node limit ends even the first iteration
+        if nodes_left == 0 || self.signals.is_some_and(SearchSignals::is_stopped) {
+            self.stopped = true;
+            return;
+        }
+        let mut time = match self.time {
+            Some(time) => time,
+            None => return,
+        };
+        // Pondered long enough already, the move can be played at once
+        let pondered = self.poll_ponderhit(&mut time) && !time.can_start_iteration();
+        self.time = Some(time);
+        self.stopped = pondered || time.out_of_time();
+    }
+
+    // Moves the search onto the engine's clock once the opponent played the expected move
+    fn poll_ponderhit(&self, time: &mut TimeManager) -> bool {
+        let hit = time.is_pondering() && self.signals.is_some_and(SearchSignals::is_ponderhit);
+        if hit {
+            time.ponderhit();
+        }
+        hit
+    }
+
+    fn is_repetition(&self, key: u64, halfmove_clock: u32) -> bool {
+        // Only positions since the last capture or pawn move can come back
+        self.keys.iter().rev().take(halfmove_clock as usize).any(|&other| other == key)
+    }
+
+    fn negamax(&mut self, position: &Position, mut depth: u32, mut alpha: i32, beta: i32, ply: usize) -> i32 {
+        self.pv[ply].clear();
+        let key = polyglot_key(position);
+        if ply > 0 && (position.halfmove_clock >= 100 || self.is_repetition(key, position.halfmove_clock) || insufficient_material(position)) {
+            return 0;
+        }
+        let in_check = position.in_check();
+        if in_check && self.options.check_extensions && ply < MAX_PLY / 2 {
+            depth += 1;
+        }
+        if depth == 0 || ply >= MAX_PLY {
+            return self.quiescence(position, alpha, beta, ply, true);
+        }
+
+        self.nodes += 1;
+        if self.nodes >= self.next_check {
+            self.check_limits();
+        }
+        if self.stopped {
+            return 0;
+        }
+
+        let mut hash_move = None;
+        if let Some(entry) = self.tt.probe(key, ply) {
+            hash_move = entry.best_move;
+            let cutoff = match entry.bound {
+                Bound::Exact => true,
+                Bound::Lower => entry.score >= beta,
+                Bound::Upper => entry.score <= alpha,
+            };
+            if ply > 0 && entry.depth >= depth && cutoff {
+                self.stats.hash_cutoffs += 1;
+                if entry.bound == Bound::Exact {
+                    self.pv[ply].extend(hash_move);
+                }
+                return entry.score;
+            }
+        }
+
+        // Right after a capture or pawn move the tables know the result, without castling
+        // rights since they don't have them. The root is left to the DTZ ranking of the moves.
+        if let Some(tablebase) = self.tablebase.filter(|_| ply > 0 && position.halfmove_clock == 0) {
+            if let Some(wdl) = tablebase.probe_wdl(position) {
+                let (score, bound) = match wdl {
+                    Wdl::Win => (TB_WIN - ply as i32, Bound::Lower),
+                    Wdl::Loss => (-TB_WIN + ply as i32, Bound::Upper),
+                    // Drawn by the fifty-move rule, still better than a draw for the winning side
+                    Wdl::CursedWin => (1, Bound::Exact),
+                    Wdl::BlessedLoss => (-1, Bound::Exact),
+                    Wdl::Draw => (0, Bound::Exact),
+                };
+                let cutoff = match bound {
+                    Bound::Exact => true,
+                    Bound::Lower => score >= beta,
+                    Bound::Upper => score <= alpha,
+                };
+                if cutoff {
+                    self.tt.store(key, (depth + 6).min(MAX_PLY as u32), bound, score, ply, None);
+                    return score;
+                }
+            }
+        }
+
+        // Outside of the principal variation a null window is searched, only whether
+        // the score is above it matters and the node can be pruned or reduced
+        let pv_node = beta - alpha > 1;
+        let mate_bound = MATE - MAX_PLY as i32;
+        let static_eval = if in_check { -INFINITY } else { evaluate(position) };
+        let prunable = !pv_node && !in_check && ply > 0;
+
+        // Far enough above beta that no move will bring it back down
+        if prunable && self.options.reverse_futility && depth <= 6 && beta.abs() < mate_bound && static_eval - 80 * depth as i32 >= beta {
+            return static_eval;
+        }
+
+        // Far enough below alpha that only a capture can help
+        if prunable && self.options.razoring && depth <= 2 && static_eval + 250 * depth as i32 <= alpha {
+            let score = self.quiescence(position, alpha, alpha + 1, ply, true);
+            if score <= alpha {
+                return score;
+            }
+        }
+
+        // Passing is a move the opponent can't make, when the score stays above beta
+        // anyway a real move will too, unless it is zugzwang: only with pieces on the
+        // board, never twice in a row, and checked by a normal search when deep
+        let previous = if ply > 0 { self.played[ply - 1] } else { None };
+        if prunable && self.options.null_move && depth >= 3 && static_eval >= beta && beta.abs() < mate_bound
+            && previous.is_some() && ply >= self.null_min_ply && has_pieces(position, position.side_to_move)
+        {
+            let reduction = 3 + depth / 4;
+            let mut next = *position;
+            next.make_null_move();
+            self.played[ply] = None;
+            self.keys.push(key);
+            let score = -self.negamax(&next, depth.saturating_sub(1 + reduction), -beta, -beta + 1, ply + 1);
+            self.keys.pop();
+            if self.stopped {
+                return 0;
+            }
+            if score >= beta {
+                let score = if score >= mate_bound { beta } else { score };
+                if depth < 10 {
+                    return score;
+                }
+                // Restored after, an outer verification may still be going on
+                let saved = self.null_min_ply;
+                self.null_min_ply = ply + 3 * (depth - reduction) as usize / 4;
+                let verified = self.negamax(position, depth - reduction, beta - 1, beta, ply);
+                self.null_min_ply = saved;
+                if verified >= beta {
+                    return score;
+                }
+            }
+        }
+
+        self.keys.push(key);
+        let mut picker = MovePicker::new(hash_move, self.heuristics.killers(ply), self.heuristics.countermove(previous));
+        let original_alpha = alpha;
+        let mut best = -INFINITY;
+        let mut best_move = None;
+        let mut moves_searched = 0;
+        let mut quiets_tried = vec![];
+        // Quiet moves can't bring the score up to alpha
+        let futile = prunable && self.options.futility && depth <= 3 && alpha.abs() < mate_bound && static_eval + 150 * depth as i32 <= alpha;
+        while let Some(mv) = picker.next(position, self.heuristics) {
+            if ply == 0 && self.excluded.contains(&mv) {
+                continue;
+            }
+            let mut next = *position;
+            next.make_move(mv);
+            let quiet = !is_tactical(position, mv) && !next.in_check();
+            if futile && quiet && moves_searched > 0 {
+                continue;
+            }
+            self.played[ply] = Some(mv);
+            moves_searched += 1;
+
+            let score = if moves_searched == 1 {
+                -self.negamax(&next, depth - 1, -beta, -alpha, ply + 1)
+            } else {
+                // Late quiet moves are searched less deep, and again fully if they turn out good
+                let reduction = if self.options.late_move_reductions && quiet && !in_check && depth >= 3 && moves_searched > 3 {
+                    let reduction = (0.75 + (depth as f64).ln() * (moves_searched as f64).ln() / 2.25) as u32;
+                    reduction.saturating_sub(pv_node as u32).min(depth - 2)
+                } else {
+                    0
+                };
+                let mut score = -self.negamax(&next, depth - 1 - reduction, -alpha - 1, -alpha, ply + 1);
+                if !self.stopped && score > alpha && reduction > 0 {
+                    score = -self.negamax(&next, depth - 1, -alpha - 1, -alpha, ply + 1);
+                }
+                if !self.stopped && score > alpha && score < beta {
+                    score = -self.negamax(&next, depth - 1, -beta, -alpha, ply + 1);
+                }
+                score
+            };
+            if self.stopped {
+                break;
+            }
+            if score > best {
+                best = score;
+                best_move = Some(mv);
+                if score > alpha {
+                    alpha = score;
+                    let mut pv = vec![mv];
+                    pv.extend_from_slice(&self.pv[ply + 1]);
+                    self.pv[ply] = pv;
+                }
+                if score >= beta {
+                    self.stats.beta_cutoffs += 1;
+                    if moves_searched == 1 {
+                        self.stats.first_move_cutoffs += 1;
+                    }
+                    if !is_tactical(position, mv) {
+                        self.heuristics.update(position.side_to_move, ply, depth, mv, previous, &quiets_tried);
+                    }
+                    break;
+                }
+            }
+            if !is_tactical(position, mv) {
+                quiets_tried.push(mv);
+            }
+        }
+        self.keys.pop();
+        if moves_searched == 0 {
+            return if in_check { -MATE + ply as i32 } else { 0 };
+        }
+
+        // The root score without some of its moves is not the score of the position
+        if !self.stopped && (ply > 0 || self.excluded.is_empty()) {
+            let bound = if best >= beta {
+                Bound::Lower
+            } else if best > original_alpha {
+                Bound::Exact
+            } else {
+                Bound::Upper
+            };
+            self.tt.store(key, depth, bound, best, ply, best_move);
+        }
+        best
+    }
+
+    // Only captures and promotions, with quiet checks at its first ply, until the position is quiet
+    fn quiescence(&mut self, position: &Position, mut alpha: i32, beta: i32, ply: usize, checks: bool) -> i32 {
+        self.nodes += 1;
+        self.stats.quiescence_nodes += 1;
+        self.pv[ply].clear();
+        if self.nodes >= self.next_check {
+            self.check_limits();
+        }
+        if self.stopped {
+            return 0;
+        }
+        if ply >= MAX_PLY {
+            return evaluate(position);
+        }
+
+        // Out of check, the side to move can stand pat instead of taking
+        let in_check = position.in_check();
+        let mut best = -INFINITY;
+        if !in_check {
+            best = evaluate(position);
+            if best >= beta {
+                return best;
+            }
+            alpha = alpha.max(best);
+        }
+
+        let mut moves = position.legal_moves();
+        moves.sort_by_cached_key(|&mv| -mvv_lva(position, mv));
+        if in_check && moves.is_empty() {
+            return -MATE + ply as i32;
+        }
+        for mv in moves {
+            let tactical = is_tactical(position, mv);
+            // Captures that lose material are not worth looking at
+            if !in_check && tactical && see(position, mv) < 0 {
+                continue;
+            }
+            let mut next = *position;
+            next.make_move(mv);
+            let quiet_check = checks && mv.promotion.is_none() && next.in_check();
+            if !in_check && !tactical && !quiet_check {
+                continue;
+            }
+
+            let score = -self.quiescence(&next, -beta, -alpha, ply + 1, false);
+            if self.stopped {
+                break;
+            }
+            if score > best {
+                best = score;
+                if score > alpha {
+                    alpha = score;
+                }
+                if score >= beta {
+                    break;
+                }
+            }
+        }
+        best
+    }
+}
+
+// Knights, bishops, rooks or queens, without them zugzwang is too likely to pass
+fn has_pieces(position: &Position, color: Color) -> bool {
+    position.board.iter().flatten().flatten().any(|piece| piece.color == color && !matches!(piece.piece_type, PieceType::Pawn | PieceType::King))
+}
+
+// Neither side can mate: bare kings or a single minor piece
+pub fn insufficient_material(position: &Position) -> bool {
+    let mut minors = 0;
+    for piece in position.board.iter().flatten().flatten() {
+        match piece.piece_type {
+            PieceType::King => (),
+            PieceType::Knight | PieceType::Bishop => minors += 1,
+            _ => return false,
+        }
+    }
+    minors <= 1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn node_limit_is_kept() {
+        let position = Position::new();
+        for nodes in [1, 500, 5000] {
+            let result = search(&position, Limits { nodes: Some(nodes), ..Limits::default() });
+            assert!(result.nodes <= nodes, "{} nodes searched for a limit of {}", result.nodes, nodes);
+            assert!(result.best_move.is_some_and(|mv| position.is_legal(mv)));
+        }
+    }
+
+    #[test]
+    fn low_skill_levels_keep_to_their_node_budget() {
+        let position = Position::new();
+        for level in [0, 3, 8] {
+            let skill = Skill::new(level);
+            let budget = skill.limit(Limits::default()).nodes.unwrap_or(u64::MAX);
+            let mut searcher = Searcher::new();
+            searcher.set_skill(skill);
+            let result = searcher.search(&position, Limits::default());
+            assert!(result.nodes <= budget, "level {} searched {} nodes for a budget of {}", level, result.nodes, budget);
+            assert!(result.best_move.is_some_and(|mv| position.is_legal(mv)));
+        }
+    }
+
+    #[test]
+    fn search_after_stopped_handle_reaches_its_depth() {
+        let position = Position::new();
+        let handle = SearchHandle::start(Searcher::new(), position, Limits::default(), |_| ());
+        while handle.best_move().is_none() {
+            thread::sleep(Duration::from_millis(1));
+        }
+        handle.stop();
+        let (mut searcher, _) = handle.join();
+        assert_eq!(searcher.search(&position, Limits::depth(5)).depth, 5);
+    }
+}
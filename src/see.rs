@@ -0,0 +1,67 @@
+use crate::eval::piece_value;
+use crate::position::{Move, Position};
+use crate::{attackers, PieceType};
+
+// Material won by the move once both sides have recaptured on its square with
+// their least valuable piece for as long as it pays, pins are not considered
+pub fn see(position: &Position, mv: Move) -> i32 {
+    let target = mv.end_pos;
+    let mut board = position.board;
+    let piece = match position.piece_at(mv.start_pos) {
+        Some(piece) => piece,
+        None => return 0,
+    };
+
+    let mut gains = vec![match position.piece_at(target) {
+        Some(victim) => piece_value(victim.piece_type),
+        None if position.is_capture(mv) => piece_value(PieceType::Pawn),
+        None => 0,
+    }];
+    if position.is_capture(mv) && position.piece_at(target).is_none() {
+        // The pawn taken en passant is beside the target
+        board[target.0][mv.start_pos.1] = None;
+    }
+    let mut on_square = piece_value(piece.piece_type);
+    if let Some(promotion) = mv.promotion {
+        gains[0] += piece_value(promotion) - piece_value(PieceType::Pawn);
+        on_square = piece_value(promotion);
+    }
+    board[mv.start_pos.0][mv.start_pos.1] = None;
+    board[target.0][target.1] = Some(piece);
+
+    let mut side = piece.color.opponent();
+    loop {
+        // Taking the piece off the board uncovers the sliders behind it
+        let least_valuable = attackers(&board, target, side).into_iter()
+            .min_by_key(|&pos| board[pos.0][pos.1].map_or(0, |piece| match piece.piece_type {
+                PieceType::King => i32::MAX,
+                piece_type => piece_value(piece_type),
+            }));
+        let from = match least_valuable {
+            Some(from) => from,
+            None => break,
+        };
+        let capturer = match board[from.0][from.1] {
+            Some(capturer) => capturer,
+            None => break,
+        };
+        // The king can only take when nothing defends the square
+        if capturer.piece_type == PieceType::King && !attackers(&board, target, side.opponent()).is_empty() {
+            break;
+        }
+
+        gains.push(on_square - gains[gains.len() - 1]);
+        on_square = piece_value(capturer.piece_type);
+        board[from.0][from.1] = None;
+        board[target.0][target.1] = Some(capturer);
+        side = side.opponent();
+    }
+
+    // Either side can stop recapturing when it would lose material
+    while gains.len() > 1 {
+        let last = gains.pop().unwrap_or(0);
+        let previous = gains.len() - 1;
+        gains[previous] = -(-gains[previous]).max(last);
+    }
+    gains[0]
+}
@@ -0,0 +1,82 @@
+use crate::rng::Rng;
+use crate::search::{Limits, PvLine};
+
+pub const MAX_LEVEL: u32 = 20;
+// Ratings the levels below full strength stand for, as UCI_Elo takes them
+pub const MIN_ELO: u32 = 800;
+pub const MAX_ELO: u32 = 2200;
+// Number of moves the weaker levels choose from
+const CANDIDATES: usize = 4;
+
+// How well the engine plays, from 0 to 20 at full strength. Weaker levels search
+// less deep and fewer nodes, then pick among the best moves with an error that
+// grows as the level goes down.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Skill {
+    level: u32,
+}
+
+impl Default for Skill {
+    fn default() -> Self {
+        Skill { level: MAX_LEVEL }
+    }
+}
+
+impl Skill {
+    pub fn new(level: u32) -> Self {
+        Skill { level: level.min(MAX_LEVEL) }
+    }
+
+    // The level playing at about this rating, full strength from MAX_ELO
+    pub fn from_elo(elo: u32) -> Self {
+        let elo = elo.clamp(MIN_ELO, MAX_ELO);
+        Skill::new((elo - MIN_ELO) * MAX_LEVEL / (MAX_ELO - MIN_ELO))
+    }
+
+    pub fn level(&self) -> u32 {
+        self.level
+    }
+
+    pub fn elo(&self) -> u32 {
+        MIN_ELO + self.level * (MAX_ELO - MIN_ELO) / MAX_LEVEL
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.level == MAX_LEVEL
+    }
+
+    // The limits of the search, cut down for the weaker levels
+    pub fn limit(&self, limits: Limits) -> Limits {
+        if self.is_full() {
+            return limits;
+        }
+        let depth = self.level + 1;
+        let nodes = 200 << (self.level / 2);
+        Limits {
+            depth: Some(limits.depth.map_or(depth, |limit| limit.min(depth))),
+            nodes: Some(limits.nodes.map_or(nodes, |limit| limit.min(nodes))),
+            ..limits
+        }
+    }
+
+    pub fn multi_pv(&self) -> usize {
+        if self.is_full() { 1 } else { CANDIDATES }
+    }
+
+    // Index of the line to play: each line gets a random bonus, larger for the
+    // weaker levels and when the scores are far apart, and the best total is played
+    pub fn pick(&self, lines: &[PvLine], rng: &mut Rng) -> usize {
+        if self.is_full() || lines.len() < 2 {
+            return 0;
+        }
+        let weakness = 120 - 2 * self.level as i32;
+        let top = lines[0].score;
+        let spread = (top - lines[lines.len() - 1].score).min(100);
+        (0..lines.len())
+            .max_by_key(|&i| {
+                let bonus = (weakness * (top - lines[i].score) + spread * rng.below(weakness as u64) as i32) / 128;
+                lines[i].score + bonus
+            })
+            .unwrap_or(0)
+    }
+}
@@ -0,0 +1,1043 @@
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
+
+use crate::position::{Move, Position};
+use crate::{Color, PieceType};
+
+// Syzygy WDL (.rtbw) and DTZ (.rtbz) probing, following the layout used by the
+// reference probing code: Huffman coded "pairs" blocks indexed by an encoding of
+// the piece squares that removes the board symmetries.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Wdl {
+    Loss,
+    // Loss that the fifty-move rule turns into a draw
+    BlessedLoss,
+    Draw,
+    // Win that the fifty-move rule turns into a draw
+    CursedWin,
+    Win,
+}
+
+impl Wdl {
+    fn from_value(value: i32) -> Self {
+        match value {
+            -2 => Wdl::Loss,
+            -1 => Wdl::BlessedLoss,
+            1 => Wdl::CursedWin,
+            2 => Wdl::Win,
+            _ => Wdl::Draw,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RootMove {
+    pub mv: Move,
+    pub wdl: Wdl,
+    // Plies to the next capture or pawn move, counted from the root, negative when losing
+    pub dtz: i32,
+    // Higher is better, certain wins share the same rank
+    pub rank: i32,
+}
+
+const WDL_MAGIC: [u8; 4] = [0x71, 0xE8, 0x23, 0x5D];
+const DTZ_MAGIC: [u8; 4] = [0xD7, 0x66, 0x0C, 0xA5];
+const MAX_PIECES: usize = 7;
+const MAX_DTZ: i32 = 1 << 18;
+
+// Flags of a PairsData
+const STM: u8 = 1;
+const MAPPED: u8 = 2;
+const WIN_PLIES: u8 = 4;
+const LOSS_PLIES: u8 = 8;
+const WIDE: u8 = 16;
+const SINGLE_VALUE: u8 = 128;
+
+// Encoding tables shared by every table
+struct Maps {
+    binomial: [[u64; 64]; MAX_PIECES],
+    map_pawns: [usize; 64],
+    lead_pawn_idx: [[u64; 64]; MAX_PIECES],
+    lead_pawns_size: [[u64; 4]; MAX_PIECES],
+    map_a1d1d4: [usize; 64],
+    map_b1h1h7: [u64; 64],
+    map_kk: [[u64; 64]; 10],
+}
+
+fn file_of(sq: usize) -> usize {
+    sq & 7
+}
+
+fn rank_of(sq: usize) -> usize {
+    sq >> 3
+}
+
+// Negative below the a1-h8 diagonal, positive above
+fn off_a1h8(sq: usize) -> i32 {
+    rank_of(sq) as i32 - file_of(sq) as i32
+}
+
+fn maps() -> &'static Maps {
+    static MAPS: OnceLock<Maps> = OnceLock::new();
+    MAPS.get_or_init(|| {
+        let mut maps = Maps {
+            binomial: [[0; 64]; MAX_PIECES],
+            map_pawns: [0; 64],
+            lead_pawn_idx: [[0; 64]; MAX_PIECES],
+            lead_pawns_size: [[0; 4]; MAX_PIECES],
+            map_a1d1d4: [0; 64],
+            map_b1h1h7: [0; 64],
+            map_kk: [[0; 64]; 10],
+        };
+
+        let mut code = 0;
+        for sq in 0..64 {
+            if off_a1h8(sq) < 0 {
+                maps.map_b1h1h7[sq] = code;
+                code += 1;
+            }
+        }
+
+        // Squares of the a1-d1-d4 triangle, the ones on the diagonal last
+        let mut diagonal = vec![];
+        let mut code = 0;
+        for sq in 0..28 {
+            if off_a1h8(sq) < 0 && file_of(sq) <= 3 {
+                maps.map_a1d1d4[sq] = code;
+                code += 1;
+            } else if off_a1h8(sq) == 0 && file_of(sq) <= 3 {
+                diagonal.push(sq);
+            }
+        }
+        for sq in diagonal {
+            maps.map_a1d1d4[sq] = code;
+            code += 1;
+        }
+
+        // The 462 legal placements of two kings with the first one in the triangle
+        let mut both_on_diagonal = vec![];
+        let mut code = 0;
+        for idx in 0..10 {
+            for s1 in 0..28 {
+                if maps.map_a1d1d4[s1] != idx || (idx == 0 && s1 != 1) {
+                    continue;
+                }
+                for s2 in 0..64 {
+                    let adjacent = file_of(s1).abs_diff(file_of(s2)) <= 1 && rank_of(s1).abs_diff(rank_of(s2)) <= 1;
+                    // With the first king on the diagonal, the second one can't be above it
+                    if adjacent || (off_a1h8(s1) == 0 && off_a1h8(s2) > 0) {
+                        continue;
+                    } else if off_a1h8(s1) == 0 && off_a1h8(s2) == 0 {
+                        both_on_diagonal.push((idx, s2));
+                    } else {
+                        maps.map_kk[idx][s2] = code;
+                        code += 1;
+                    }
+                }
+            }
+        }
+        for (idx, s2) in both_on_diagonal {
+            maps.map_kk[idx][s2] = code;
+            code += 1;
+        }
+
+        maps.binomial[0][0] = 1;
+        for n in 1..64 {
+            for k in 0..MAX_PIECES.min(n + 1) {
+                maps.binomial[k][n] = if k > 0 { maps.binomial[k - 1][n - 1] } else { 0 } + if k < n { maps.binomial[k][n - 1] } else { 0 };
+            }
+        }
+
+        // Pawn squares numbered from the edges, the leading pawn has the highest one
+        let mut available_squares = 48;
+        for lead_pawns_count in 1..MAX_PIECES {
+            for file in 0..4 {
+                let mut idx = 0;
+                for rank in 1..7 {
+                    let sq = rank * 8 + file;
+                    if lead_pawns_count == 1 {
+                        available_squares -= 2;
+                        maps.map_pawns[sq] = available_squares + 1;
+                        maps.map_pawns[sq ^ 7] = available_squares;
+                    }
+                    maps.lead_pawn_idx[lead_pawns_count][sq] = idx;
+                    idx += maps.binomial[lead_pawns_count - 1][maps.map_pawns[sq]];
+                }
+                maps.lead_pawns_size[lead_pawns_count][file] = idx;
+            }
+        }
+
+        maps
+    })
+}
+
+#[cfg(unix)]
+fn read_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<()> {
+    use std::os::unix::fs::FileExt;
+    file.read_exact_at(buf, offset)
+}
+
+#[cfg(windows)]
+fn read_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<()> {
+    use std::os::windows::fs::FileExt;
+    let mut done = 0;
+    while done < buf.len() {
+        match file.seek_read(&mut buf[done..], offset + done as u64)? {
+            0 => return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "table is truncated")),
+            n => done += n,
+        }
+    }
+    Ok(())
+}
+
+fn read_u16(file: &File, offset: u64) -> io::Result<u16> {
+    let mut bytes = [0; 2];
+    read_at(file, &mut bytes, offset)?;
+    Ok(u16::from_le_bytes(bytes))
+}
+
+fn read_u32(file: &File, offset: u64) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    read_at(file, &mut bytes, offset)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+// Sequential reads of the table header
+struct Reader<'a> {
+    file: &'a File,
+    offset: u64,
+}
+
+impl Reader<'_> {
+    fn bytes(&mut self, len: usize) -> io::Result<Vec<u8>> {
+        let mut bytes = vec![0; len];
+        read_at(self.file, &mut bytes, self.offset)?;
+        self.offset += len as u64;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> io::Result<u16> {
+        let value = read_u16(self.file, self.offset)?;
+        self.offset += 2;
+        Ok(value)
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        let value = read_u32(self.file, self.offset)?;
+        self.offset += 4;
+        Ok(value)
+    }
+}
+
+// One compressed sub-table, for a side to move and a leading pawn file
+#[derive(Clone, Default)]
+struct PairsData {
+    flags: u8,
+    min_sym_len: u8,
+    block_size: u64,
+    span: u64,
+    num_blocks: u64,
+    block_length_size: u64,
+    sparse_index_size: u64,
+    // Symbol of each length with the lowest value
+    lowest_sym: Vec<u16>,
+    // Left and right symbols each symbol expands to, 12 bits each
+    btree: Vec<[u8; 3]>,
+    base64: Vec<u64>,
+    // Number of values minus one a symbol stands for
+    symlen: Vec<u8>,
+    // File offsets of the sparse index, the block lengths and the compressed data
+    sparse_index: u64,
+    block_length: u64,
+    data: u64,
+    // The order of the pieces defines the groups
+    pieces: [u8; MAX_PIECES],
+    group_idx: [u64; MAX_PIECES + 1],
+    group_len: [usize; MAX_PIECES + 1],
+    // DTZ only: start of the value maps for Win, Loss, CursedWin and BlessedLoss
+    map_idx: [u64; 4],
+}
+
+fn left(lr: [u8; 3]) -> usize {
+    (((lr[1] & 0xF) as usize) << 8) | lr[0] as usize
+}
+
+fn right(lr: [u8; 3]) -> usize {
+    ((lr[2] as usize) << 4) | (lr[1] >> 4) as usize
+}
+
+impl PairsData {
+    fn set_symlen(&mut self, sym: usize, visited: &mut [bool]) -> u8 {
+        visited[sym] = true;
+        let sr = right(self.btree[sym]);
+        if sr == 0xFFF {
+            return 0;
+        }
+        let sl = left(self.btree[sym]);
+        if !visited[sl] {
+            self.symlen[sl] = self.set_symlen(sl, visited);
+        }
+        if !visited[sr] {
+            self.symlen[sr] = self.set_symlen(sr, visited);
+        }
+        self.symlen[sl].wrapping_add(self.symlen[sr]).wrapping_add(1)
+    }
+
+    fn set_sizes(&mut self, reader: &mut Reader) -> io::Result<()> {
+        self.flags = reader.u8()?;
+        if self.flags & SINGLE_VALUE != 0 {
+            // The single value is stored in place of the symbol length
+            self.min_sym_len = reader.u8()?;
+            return Ok(());
+        }
+
+        let groups = self.group_len.iter().position(|&len| len == 0).unwrap_or(MAX_PIECES);
+        let tb_size = self.group_idx[groups];
+        self.block_size = 1 << reader.u8()?;
+        self.span = 1 << reader.u8()?;
+        self.sparse_index_size = tb_size.div_ceil(self.span);
+        let padding = reader.u8()? as u64;
+        self.num_blocks = reader.u32()? as u64;
+        self.block_length_size = self.num_blocks + padding;
+        let max_sym_len = reader.u8()?;
+        self.min_sym_len = reader.u8()?;
+        if max_sym_len < self.min_sym_len || self.min_sym_len == 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid symbol lengths"));
+        }
+
+        let lengths = (max_sym_len - self.min_sym_len + 1) as usize;
+        self.lowest_sym = (0..lengths).map(|_| reader.u16()).collect::<io::Result<Vec<u16>>>()?;
+
+        // Canonical Huffman code: longer symbols have lower values, base64[i] is the
+        // lowest symbol of length i + min_sym_len padded to 64 bits
+        self.base64 = vec![0; lengths];
+        for i in (0..lengths - 1).rev() {
+            self.base64[i] = self.base64[i + 1].wrapping_add(self.lowest_sym[i] as u64).wrapping_sub(self.lowest_sym[i + 1] as u64) / 2;
+        }
+        for (i, base) in self.base64.iter_mut().enumerate() {
+            *base = base.checked_shl(64 - i as u32 - self.min_sym_len as u32).unwrap_or(0);
+        }
+
+        let symbols = reader.u16()? as usize;
+        self.btree = reader.bytes(symbols * 3)?.chunks(3).map(|lr| [lr[0], lr[1], lr[2]]).collect();
+        reader.offset += (symbols & 1) as u64;
+
+        self.symlen = vec![0; symbols];
+        let mut visited = vec![false; symbols];
+        for sym in 0..symbols {
+            if !visited[sym] {
+                self.symlen[sym] = self.set_symlen(sym, &mut visited);
+            }
+        }
+        Ok(())
+    }
+
+    fn decompress(&self, file: &File, idx: u64) -> io::Result<i32> {
+        if self.flags & SINGLE_VALUE != 0 {
+            return Ok(self.min_sym_len as i32);
+        }
+
+        // Find the block holding idx, starting from the nearest sparse index entry
+        let k = idx / self.span;
+        let mut block = read_u32(file, self.sparse_index + 6 * k)? as u64;
+        let mut offset = read_u16(file, self.sparse_index + 6 * k + 4)? as i64;
+        offset += (idx % self.span) as i64 - (self.span / 2) as i64;
+        let block_length = |block: u64| -> io::Result<i64> {
+            Ok(read_u16(file, self.block_length + 2 * block)? as i64)
+        };
+        while offset < 0 {
+            block = block.checked_sub(1).ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "corrupted sparse index"))?;
+            offset += block_length(block)? + 1;
+        }
+        loop {
+            let length = block_length(block)?;
+            if offset <= length {
+                break;
+            }
+            offset -= length + 1;
+            block += 1;
+        }
+
+        let mut bytes = vec![0; self.block_size as usize + 8];
+        let start = self.data + block * self.block_size;
+        let available = (file.metadata()?.len().saturating_sub(start) as usize).min(bytes.len());
+        read_at(file, &mut bytes[..available], start)?;
+
+        let mut buf64 = u64::from_be_bytes(bytes[0..8].try_into().unwrap());
+        let mut buf64_size = 64;
+        let mut next_word = 8;
+        let min_sym_len = self.min_sym_len as u32;
+        let mut sym;
+        loop {
+            let mut len = 0;
+            while buf64 < self.base64[len] {
+                len += 1;
+            }
+            sym = ((buf64 - self.base64[len]) >> (64 - len as u32 - min_sym_len)) as usize;
+            sym += self.lowest_sym[len] as usize;
+            if sym >= self.symlen.len() {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "corrupted block"));
+            }
+            if offset < self.symlen[sym] as i64 + 1 {
+                break;
+            }
+            offset -= self.symlen[sym] as i64 + 1;
+            let len = len as u32 + min_sym_len;
+            buf64 = buf64.checked_shl(len).unwrap_or(0);
+            buf64_size -= len as i32;
+            if buf64_size <= 32 {
+                buf64_size += 32;
+                let word = match bytes.get(next_word..next_word + 4) {
+                    Some(word) => u32::from_be_bytes(word.try_into().unwrap()),
+                    None => 0,
+                };
+                buf64 |= (word as u64) << (64 - buf64_size);
+                next_word += 4;
+            }
+        }
+
+        // Walk down the pairs until the symbol stands for a single value
+        while self.symlen[sym] != 0 {
+            let sl = left(self.btree[sym]);
+            if offset < self.symlen[sl] as i64 + 1 {
+                sym = sl;
+            } else {
+                offset -= self.symlen[sl] as i64 + 1;
+                sym = right(self.btree[sym]);
+            }
+        }
+        Ok(left(self.btree[sym]) as i32)
+    }
+}
+
+// Material of a table, as given by its file name like "KRPvKR"
+#[derive(Clone)]
+struct Material {
+    white: String,
+    black: String,
+    piece_count: usize,
+    has_pawns: bool,
+    has_unique_pieces: bool,
+    // [leading color, other color]
+    pawn_count: [usize; 2],
+}
+
+impl Material {
+    fn from_name(name: &str) -> Option<Self> {
+        let (white, black) = name.split_once('v')?;
+        let count = |side: &str, c: char| side.chars().filter(|&other| other == c).count();
+        let has_unique_pieces = [white, black].iter().any(|side| "QRBNP".chars().any(|c| count(side, c) == 1));
+        let (white_pawns, black_pawns) = (count(white, 'P'), count(black, 'P'));
+        // The side with less pawns leads, it gives a better compression
+        let white_leads = black_pawns == 0 || (white_pawns > 0 && black_pawns >= white_pawns);
+        Some(Material {
+            white: white.to_string(),
+            black: black.to_string(),
+            piece_count: white.len() + black.len(),
+            has_pawns: white_pawns + black_pawns > 0,
+            has_unique_pieces,
+            pawn_count: if white_leads { [white_pawns, black_pawns] } else { [black_pawns, white_pawns] },
+        })
+    }
+
+    fn symmetric(&self) -> bool {
+        self.white == self.black
+    }
+}
+
+struct Table {
+    file: File,
+    dtz: bool,
+    material: Material,
+    // [side to move][leading pawn file], only [0][0] without pawns
+    items: [[PairsData; 4]; 2],
+    // DTZ only: offset of the value maps
+    map: u64,
+}
+
+impl Table {
+    fn open(path: &Path, material: Material, dtz: bool) -> io::Result<Self> {
+        let file = File::open(path)?;
+        let mut table = Table { file, dtz, material, items: Default::default(), map: 0 };
+        table.parse()?;
+        Ok(table)
+    }
+
+    fn get(&self, stm: usize, file: usize) -> &PairsData {
+        let side = if self.dtz { 0 } else { stm };
+        &self.items[side][if self.material.has_pawns { file } else { 0 }]
+    }
+
+    fn parse(&mut self) -> io::Result<()> {
+        let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message.to_string());
+        let file = self.file.try_clone()?;
+        let mut reader = Reader { file: &file, offset: 0 };
+        let magic = reader.bytes(4)?;
+        if magic != if self.dtz { DTZ_MAGIC } else { WDL_MAGIC } {
+            return Err(invalid("bad magic"));
+        }
+
+        let flags = reader.u8()?;
+        if (flags & 2 != 0) != self.material.has_pawns || (flags & 1 != 0) == self.material.symmetric() {
+            return Err(invalid("table does not match its name"));
+        }
+        let sides = if !self.dtz && !self.material.symmetric() { 2 } else { 1 };
+        let files = if self.material.has_pawns { 4 } else { 1 };
+        let pawns_on_both_sides = self.material.has_pawns && self.material.pawn_count[1] > 0;
+        let piece_count = self.material.piece_count;
+        if piece_count > MAX_PIECES {
+            return Err(invalid("too many pieces"));
+        }
+
+        for f in 0..files {
+            let first = reader.u8()?;
+            let second = if pawns_on_both_sides { reader.u8()? } else { 0xFF };
+            let order = [[first & 0xF, second & 0xF], [first >> 4, second >> 4]];
+            for k in 0..piece_count {
+                let byte = reader.u8()?;
+                for (i, side) in self.items.iter_mut().enumerate().take(sides) {
+                    side[f].pieces[k] = if i == 1 { byte >> 4 } else { byte & 0xF };
+                }
+            }
+            for (i, order) in order.into_iter().enumerate().take(sides) {
+                self.set_groups(i, f, order);
+            }
+        }
+        reader.offset += reader.offset & 1;
+
+        for f in 0..files {
+            for i in 0..sides {
+                self.items[i][f].set_sizes(&mut reader)?;
+            }
+        }
+
+        if self.dtz {
+            self.map = reader.offset;
+            for f in 0..files {
+                let flags = self.items[0][f].flags;
+                if flags & MAPPED == 0 {
+                    continue;
+                }
+                for i in 0..4 {
+                    if flags & WIDE != 0 {
+                        reader.offset += reader.offset & 1;
+                        self.items[0][f].map_idx[i] = (reader.offset - self.map) / 2 + 1;
+                        let len = reader.u16()? as u64;
+                        reader.offset += 2 * len;
+                    } else {
+                        self.items[0][f].map_idx[i] = reader.offset - self.map + 1;
+                        let len = reader.u8()? as u64;
+                        reader.offset += len;
+                    }
+                }
+            }
+            reader.offset += reader.offset & 1;
+        }
+
+        for f in 0..files {
+            for i in 0..sides {
+                self.items[i][f].sparse_index = reader.offset;
+                reader.offset += self.items[i][f].sparse_index_size * 6;
+            }
+        }
+        for f in 0..files {
+            for i in 0..sides {
+                self.items[i][f].block_length = reader.offset;
+                reader.offset += self.items[i][f].block_length_size * 2;
+            }
+        }
+        for f in 0..files {
+            for i in 0..sides {
+                reader.offset = (reader.offset + 0x3F) & !0x3F;
+                self.items[i][f].data = reader.offset;
+                reader.offset += self.items[i][f].num_blocks * self.items[i][f].block_size;
+            }
+        }
+        Ok(())
+    }
+
+    // Split the pieces in groups of identical pieces and compute the size of the
+    // index of each group, in the order given by the table
+    fn set_groups(&mut self, side: usize, file: usize, order: [u8; 2]) {
+        let maps = maps();
+        let material = &self.material;
+        let d = &mut self.items[side][file];
+        let mut n = 0;
+        let mut first_len: i32 = if material.has_pawns { 0 } else if material.has_unique_pieces { 3 } else { 2 };
+        d.group_len[0] = 1;
+        for i in 1..material.piece_count {
+            first_len -= 1;
+            if first_len > 0 || d.pieces[i] == d.pieces[i - 1] {
+                d.group_len[n] += 1;
+            } else {
+                n += 1;
+                d.group_len[n] = 1;
+            }
+        }
+        n += 1;
+        d.group_len[n] = 0;
+
+        let pawns_on_both_sides = material.has_pawns && material.pawn_count[1] > 0;
+        let mut next = if pawns_on_both_sides { 2 } else { 1 };
+        let mut free_squares = 64 - d.group_len[0] - if pawns_on_both_sides { d.group_len[1] } else { 0 };
+        let mut idx = 1;
+        let mut k = 0;
+        while next < n || k == order[0] as usize || k == order[1] as usize {
+            if k == order[0] as usize {
+                // Leading pawns or pieces
+                d.group_idx[0] = idx;
+                idx *= if material.has_pawns {
+                    maps.lead_pawns_size[d.group_len[0]][file]
+                } else if material.has_unique_pieces {
+                    31332
+                } else {
+                    462
+                };
+            } else if k == order[1] as usize {
+                // Remaining pawns
+                d.group_idx[1] = idx;
+                idx *= maps.binomial[d.group_len[1]][48 - d.group_len[0]];
+            } else {
+                // Remaining pieces
+                d.group_idx[next] = idx;
+                idx *= maps.binomial[d.group_len[next]][free_squares];
+                free_squares -= d.group_len[next];
+                next += 1;
+            }
+            k += 1;
+        }
+        d.group_idx[n] = idx;
+    }
+
+    // Value stored for the position, None when a DTZ table only has the other side to move
+    fn probe(&self, position: &Position, wdl: i32) -> io::Result<Option<i32>> {
+        let maps = maps();
+        let mut squares = [0usize; MAX_PIECES];
+        let mut pieces = [0u8; MAX_PIECES];
+        let mut size = 0;
+        let mut lead_pawns_count = 0;
+        let mut tb_file = 0;
+
+        let white_material = material_string(position, Color::White);
+        let black_to_move = position.side_to_move == Color::Black;
+        // Tables are computed with white as the stronger side, and symmetric ones only with white to move
+        let flip = (self.material.symmetric() && black_to_move) || white_material != self.material.white;
+        let flip_color = if flip { 8 } else { 0 };
+        let flip_squares = if flip { 56 } else { 0 };
+        let stm = (flip ^ black_to_move) as usize;
+
+        let mut occupied = vec![];
+        for sq in 0..64 {
+            if let Some(piece) = position.board[file_of(sq)][rank_of(sq)] {
+                occupied.push((sq, piece_code(piece.piece_type, piece.color)));
+            }
+        }
+
+        let mut lead_pawns = vec![];
+        if self.material.has_pawns {
+            // Pawns of the leading color come first, the one with the highest map_pawns leads
+            let lead_pawn = self.get(0, 0).pieces[0] ^ flip_color;
+            for &(sq, code) in &occupied {
+                if code == lead_pawn {
+                    lead_pawns.push(sq);
+                    squares[size] = sq ^ flip_squares;
+                    size += 1;
+                }
+            }
+            lead_pawns_count = size;
+            let mut lead = 0;
+            for i in 1..lead_pawns_count {
+                if maps.map_pawns[squares[i]] > maps.map_pawns[squares[lead]] {
+                    lead = i;
+                }
+            }
+            squares.swap(0, lead);
+            tb_file = file_of(squares[0]).min(7 - file_of(squares[0]));
+        }
+
+        if self.dtz {
+            let flags = self.get(stm, tb_file).flags;
+            if (flags & STM) as usize != stm && (self.material.has_pawns || !self.material.symmetric()) {
+                return Ok(None);
+            }
+        }
+
+        for &(sq, code) in &occupied {
+            if lead_pawns.contains(&sq) {
+                continue;
+            }
+            if size == MAX_PIECES {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, "too many pieces"));
+            }
+            squares[size] = sq ^ flip_squares;
+            pieces[size] = code ^ flip_color;
+            size += 1;
+        }
+
+        let d = self.get(stm, tb_file);
+
+        // Same piece order as the table
+        for i in lead_pawns_count..size.saturating_sub(1) {
+            for j in i + 1..size {
+                if d.pieces[i] == pieces[j] {
+                    pieces.swap(i, j);
+                    squares.swap(i, j);
+                    break;
+                }
+            }
+        }
+
+        // The leading piece goes to the a-d files
+        if file_of(squares[0]) > 3 {
+            for sq in squares.iter_mut().take(size) {
+                *sq ^= 7;
+            }
+        }
+
+        let mut idx;
+        if self.material.has_pawns {
+            idx = maps.lead_pawn_idx[lead_pawns_count][squares[0]];
+            squares[1..lead_pawns_count].sort_by_key(|&sq| maps.map_pawns[sq]);
+            for (i, &sq) in squares.iter().enumerate().take(lead_pawns_count).skip(1) {
+                idx += maps.binomial[i][maps.map_pawns[sq]];
+            }
+        } else {
+            // Then to the first four ranks
+            if rank_of(squares[0]) > 3 {
+                for sq in squares.iter_mut().take(size) {
+                    *sq ^= 56;
+                }
+            }
+            // And the first piece of the leading group off the diagonal below it
+            for i in 0..d.group_len[0] {
+                if off_a1h8(squares[i]) == 0 {
+                    continue;
+                }
+                if off_a1h8(squares[i]) > 0 {
+                    for sq in squares.iter_mut().take(size).skip(i) {
+                        *sq = ((*sq >> 3) | (*sq << 3)) & 63;
+                    }
+                }
+                break;
+            }
+
+            if self.material.has_unique_pieces {
+                let adjust1 = (squares[1] > squares[0]) as usize;
+                let adjust2 = (squares[2] > squares[0]) as usize + (squares[2] > squares[1]) as usize;
+                idx = if off_a1h8(squares[0]) != 0 {
+                    (maps.map_a1d1d4[squares[0]] as u64 * 63 + (squares[1] - adjust1) as u64) * 62 + (squares[2] - adjust2) as u64
+                } else if off_a1h8(squares[1]) != 0 {
+                    (6 * 63 + rank_of(squares[0]) as u64 * 28 + maps.map_b1h1h7[squares[1]]) * 62 + (squares[2] - adjust2) as u64
+                } else if off_a1h8(squares[2]) != 0 {
+                    6 * 63 * 62 + 4 * 28 * 62 + rank_of(squares[0]) as u64 * 7 * 28 + (rank_of(squares[1]) - adjust1) as u64 * 28 + maps.map_b1h1h7[squares[2]]
+                } else {
+                    6 * 63 * 62 + 4 * 28 * 62 + 4 * 7 * 28 + rank_of(squares[0]) as u64 * 7 * 6 + (rank_of(squares[1]) - adjust1) as u64 * 6 + (rank_of(squares[2]) - adjust2) as u64
+                };
+            } else {
+                idx = maps.map_kk[maps.map_a1d1d4[squares[0]]][squares[1]];
+            }
+        }
+
+        // Remaining groups, each square counted among the ones the previous groups left free
+        idx *= d.group_idx[0];
+        let mut group_start = d.group_len[0];
+        let mut remaining_pawns = self.material.has_pawns && self.material.pawn_count[1] > 0;
+        let mut next = 1;
+        while d.group_len[next] != 0 {
+            let group_end = group_start + d.group_len[next];
+            squares[group_start..group_end].sort();
+            let mut n = 0;
+            for i in 0..d.group_len[next] {
+                let sq = squares[group_start + i];
+                let adjust = squares[..group_start].iter().filter(|&&other| sq > other).count();
+                n += maps.binomial[i + 1][sq - adjust - if remaining_pawns { 8 } else { 0 }];
+            }
+            remaining_pawns = false;
+            idx += n * d.group_idx[next];
+            group_start = group_end;
+            next += 1;
+        }
+
+        let value = d.decompress(&self.file, idx)?;
+        if !self.dtz {
+            return Ok(Some(value - 2));
+        }
+
+        // DTZ values may go through a map and be stored in moves instead of plies
+        let d = self.get(0, tb_file);
+        let mut value = value as u64;
+        if d.flags & MAPPED != 0 {
+            let map_idx = d.map_idx[[1, 3, 0, 2, 0][(wdl + 2) as usize]];
+            value = if d.flags & WIDE != 0 {
+                read_u16(&self.file, self.map + 2 * (map_idx + value))? as u64
+            } else {
+                let mut byte = [0];
+                read_at(&self.file, &mut byte, self.map + map_idx + value)?;
+                byte[0] as u64
+            };
+        }
+        let mut value = value as i32;
+        if (wdl == 2 && d.flags & WIN_PLIES == 0) || (wdl == -2 && d.flags & LOSS_PLIES == 0) || wdl == 1 || wdl == -1 {
+            value *= 2;
+        }
+        Ok(Some(value + 1))
+    }
+}
+
+fn piece_code(piece_type: PieceType, color: Color) -> u8 {
+    let code = match piece_type {
+        PieceType::Pawn => 1,
+        PieceType::Knight => 2,
+        PieceType::Bishop => 3,
+        PieceType::Rook => 4,
+        PieceType::Queen => 5,
+        PieceType::King => 6,
+    };
+    match color {
+        Color::White => code,
+        Color::Black => code + 8,
+    }
+}
+
+// Pieces of one color from the king down, as in table names: "KRP"
+fn material_string(position: &Position, color: Color) -> String {
+    let mut material = String::new();
+    for (piece_type, letter) in [(PieceType::King, 'K'), (PieceType::Queen, 'Q'), (PieceType::Rook, 'R'), (PieceType::Bishop, 'B'), (PieceType::Knight, 'N'), (PieceType::Pawn, 'P')] {
+        for file in position.board.iter() {
+            for piece in file.iter().flatten() {
+                if piece.piece_type == piece_type && piece.color == color {
+                    material.push(letter);
+                }
+            }
+        }
+    }
+    material
+}
+
+fn piece_count(position: &Position) -> usize {
+    position.board.iter().map(|file| file.iter().flatten().count()).sum()
+}
+
+// DTZ of the move that resets the fifty-move counter
+fn dtz_before_zeroing(wdl: i32) -> i32 {
+    match wdl {
+        2 => 1,
+        1 => 101,
+        -1 => -101,
+        -2 => -1,
+        _ => 0,
+    }
+}
+
+type TableCache = Mutex<HashMap<String, Option<Arc<Table>>>>;
+
+// Syzygy tables of a directory, opened the first time they are needed
+pub struct Tablebase {
+    directory: PathBuf,
+    max_pieces: usize,
+    tables: TableCache,
+}
+
+impl Tablebase {
+    pub fn open<P: AsRef<Path>>(directory: P) -> io::Result<Self> {
+        let directory = directory.as_ref().to_path_buf();
+        let mut max_pieces = 0;
+        for entry in fs::read_dir(&directory)? {
+            let name = entry?.file_name().to_string_lossy().to_string();
+            if let Some(stem) = name.strip_suffix(".rtbw") {
+                if let Some(material) = Material::from_name(stem) {
+                    if material.piece_count <= MAX_PIECES {
+                        max_pieces = max_pieces.max(material.piece_count);
+                    }
+                }
+            }
+        }
+        Ok(Tablebase { directory, max_pieces, tables: Mutex::new(HashMap::new()) })
+    }
+
+    // Largest number of pieces, kings included, of the tables found
+    pub fn max_pieces(&self) -> usize {
+        self.max_pieces
+    }
+
+    fn table(&self, position: &Position, dtz: bool) -> Option<Arc<Table>> {
+        let white = material_string(position, Color::White);
+        let black = material_string(position, Color::Black);
+        let extension = if dtz { "rtbz" } else { "rtbw" };
+        let mut tables = self.tables.lock().unwrap_or_else(|e| e.into_inner());
+        for name in [format!("{}v{}", white, black), format!("{}v{}", black, white)] {
+            let file_name = format!("{}.{}", name, extension);
+            if let Some(table) = tables.get(&file_name) {
+                if table.is_some() {
+                    return table.clone();
+                }
+                continue;
+            }
+            let path = self.directory.join(&file_name);
+            let table = match Material::from_name(&name) {
+                Some(material) if path.exists() => Table::open(&path, material, dtz).ok().map(Arc::new),
+                _ => None,
+            };
+            tables.insert(file_name, table.clone());
+            if table.is_some() {
+                return table;
+            }
+        }
+        None
+    }
+
+    fn probe_table(&self, position: &Position, dtz: bool, wdl: i32) -> Option<Option<i32>> {
+        if piece_count(position) == 2 {
+            // Bare kings
+            return Some(Some(0));
+        }
+        self.table(position, dtz)?.probe(position, wdl).ok()
+    }
+
+    // Tables don't store positions where a capture (or a pawn move for DTZ) is best,
+    // so look at those moves first. The flag is set when such a move is the best one.
+    fn search(&self, position: &Position, check_zeroing_moves: bool) -> Option<(i32, bool)> {
+        let moves = position.legal_moves();
+        let mut best = -2;
+        let mut count = 0;
+        for &mv in &moves {
+            let is_pawn = matches!(position.piece_at(mv.start_pos), Some(piece) if piece.piece_type == PieceType::Pawn);
+            if !position.is_capture(mv) && (!check_zeroing_moves || !is_pawn) {
+                continue;
+            }
+            count += 1;
+            let mut next = *position;
+            next.make_move(mv);
+            let value = -self.search(&next, false)?.0;
+            if value > best {
+                best = value;
+                if value >= 2 {
+                    return Some((value, true));
+                }
+            }
+        }
+
+        let no_more_moves = count > 0 && count == moves.len();
+        let value = if no_more_moves { best } else { self.probe_table(position, false, 0)?? };
+        if best >= value {
+            return Some((best, best > 0 || no_more_moves));
+        }
+        Some((value, false))
+    }
+
+    fn can_probe(&self, position: &Position) -> bool {
+        // Tables don't know about castling
+        let castling = [(Color::White, 0), (Color::White, 7), (Color::Black, 0), (Color::Black, 7)].into_iter().any(|(color, rook_file)| position.can_castle(color, rook_file));
+        !castling && piece_count(position) <= self.max_pieces
+    }
+
+    // Win, draw or loss for the side to move, cheap enough to be used inside search
+    pub fn probe_wdl(&self, position: &Position) -> Option<Wdl> {
+        if !self.can_probe(position) {
+            return None;
+        }
+        self.search(position, false).map(|(wdl, _)| Wdl::from_value(wdl))
+    }
+
+    // Plies to the next capture or pawn move with best play, negative when losing
+    pub fn probe_dtz(&self, position: &Position) -> Option<i32> {
+        if !self.can_probe(position) {
+            return None;
+        }
+        self.dtz(position)
+    }
+
+    fn dtz(&self, position: &Position) -> Option<i32> {
+        let (wdl, zeroing_is_best) = self.search(position, true)?;
+        if wdl == 0 {
+            return Some(0);
+        }
+        if zeroing_is_best {
+            return Some(dtz_before_zeroing(wdl));
+        }
+        if let Some(dtz) = self.probe_table(position, true, wdl)? {
+            let cursed = if wdl == 1 || wdl == -1 { 100 } else { 0 };
+            return Some((dtz + cursed) * wdl.signum());
+        }
+
+        // The table only has the other side to move: look one ply ahead
+        let mut min_dtz = 0xFFFF;
+        for mv in position.legal_moves() {
+            let is_pawn = matches!(position.piece_at(mv.start_pos), Some(piece) if piece.piece_type == PieceType::Pawn);
+            let zeroing = is_pawn || position.is_capture(mv);
+            let mut next = *position;
+            next.make_move(mv);
+            let mut dtz = if zeroing {
+                -dtz_before_zeroing(self.search(&next, false)?.0)
+            } else {
+                -self.dtz(&next)?
+            };
+            if dtz == 1 && next.is_checkmate() {
+                min_dtz = 1;
+            }
+            if !zeroing {
+                dtz += dtz.signum();
+            }
+            if dtz < min_dtz && dtz.signum() == wdl.signum() {
+                min_dtz = dtz;
+            }
+        }
+        Some(if min_dtz == 0xFFFF { -1 } else { min_dtz })
+    }
+
+    // Rank every legal move of the root with DTZ, so that wins are converted before
+    // the fifty-move rule and losses are dragged past it. Best moves come first.
+    pub fn probe_root(&self, position: &Position) -> Option<Vec<RootMove>> {
+        if !self.can_probe(position) {
+            return None;
+        }
+        let halfmove_clock = position.halfmove_clock as i32;
+        let mut root_moves = vec![];
+        for mv in position.legal_moves() {
+            let mut next = *position;
+            next.make_move(mv);
+            let mut dtz = if next.halfmove_clock == 0 {
+                dtz_before_zeroing(-self.search(&next, false)?.0)
+            } else {
+                let dtz = -self.dtz(&next)?;
+                dtz + dtz.signum()
+            };
+            if dtz == 2 && next.is_checkmate() {
+                dtz = 1;
+            }
+
+            let rank = if dtz > 0 {
+                if dtz + halfmove_clock <= 99 { MAX_DTZ } else { MAX_DTZ - (dtz + halfmove_clock) }
+            } else if dtz < 0 {
+                if -dtz * 2 + halfmove_clock < 100 { -MAX_DTZ } else { -MAX_DTZ + (-dtz + halfmove_clock) }
+            } else {
+                0
+            };
+            let bound = MAX_DTZ - 100;
+            let wdl = if rank >= bound {
+                Wdl::Win
+            } else if rank > 0 {
+                Wdl::CursedWin
+            } else if rank == 0 {
+                Wdl::Draw
+            } else if rank > -bound {
+                Wdl::BlessedLoss
+            } else {
+                Wdl::Loss
+            };
+            root_moves.push(RootMove { mv, wdl, dtz, rank });
+        }
+
+        // Among equal ranks, win quickly and lose slowly
+        root_moves.sort_by_key(|root_move| (std::cmp::Reverse(root_move.rank), if root_move.dtz > 0 { root_move.dtz } else { -root_move.dtz.abs() }));
+        Some(root_moves)
+    }
+}
@@ -0,0 +1,75 @@
+use std::time::{Duration, Instant};
+
+use crate::search::Limits;
+use crate::Color;
+
+// Kept back from the clock for the time it takes to send the move
+const MOVE_OVERHEAD: Duration = Duration::from_millis(30);
+// Assumed number of moves left in the game when there is no movestogo
+const DEFAULT_MOVES_TO_GO: u32 = 30;
+
+// When to stop searching: no new iteration after the soft limit, and an
+// iteration in progress is abandoned at the hard limit. While pondering neither
+// applies. After a ponderhit the soft limit still counts from the start, so that
+// the time spent pondering is taken off it, but the hard one only counts from the
+// ponderhit, when the engine's clock started.
+#[derive(Debug, Clone, Copy)]
+pub struct TimeManager {
+    start: Instant,
+    clock_start: Instant,
+    pondering: bool,
+    soft: Option<Duration>,
+    hard: Option<Duration>,
+}
+
+impl TimeManager {
+    pub fn new(limits: &Limits, side_to_move: Color) -> Self {
+        let (soft, hard) = Self::allocate(limits, side_to_move);
+        let start = Instant::now();
+        TimeManager { start, clock_start: start, pondering: limits.ponder, soft, hard }
+    }
+
+    // Soft and hard limits out of the clock
+    fn allocate(limits: &Limits, side_to_move: Color) -> (Option<Duration>, Option<Duration>) {
+        if let Some(movetime) = limits.movetime {
+            let movetime = movetime.saturating_sub(MOVE_OVERHEAD).max(Duration::from_millis(1));
+            return (Some(movetime), Some(movetime));
+        }
+
+        let (time, increment) = match side_to_move {
+            Color::White => (limits.wtime, limits.winc),
+            Color::Black => (limits.btime, limits.binc),
+        };
+        let time = match time {
+            Some(time) => time,
+            None => return (None, None),
+        };
+        let available = time.saturating_sub(MOVE_OVERHEAD).max(Duration::from_millis(1));
+        let moves_to_go = limits.movestogo.unwrap_or(DEFAULT_MOVES_TO_GO).clamp(1, 50);
+        let hard = (available / moves_to_go * 3 + increment).min(available);
+        let soft = (available / moves_to_go + increment * 3 / 4).min(hard);
+        (Some(soft), Some(hard))
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        self.start.elapsed()
+    }
+
+    pub fn is_pondering(&self) -> bool {
+        self.pondering
+    }
+
+    // The opponent played the expected move, the search is now on the engine's clock
+    pub fn ponderhit(&mut self) {
+        self.pondering = false;
+        self.clock_start = Instant::now();
+    }
+
+    pub fn can_start_iteration(&self) -> bool {
+        self.pondering || self.soft.is_none_or(|soft| self.elapsed() < soft)
+    }
+
+    pub fn out_of_time(&self) -> bool {
+        !self.pondering && self.hard.is_some_and(|hard| self.clock_start.elapsed() >= hard)
+    }
+}
@@ -0,0 +1,237 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::position::Move;
+use crate::search::{MAX_PLY, TB_WIN};
+use crate::PieceType;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bound {
+    // The score is at most this (no move reached alpha)
+    Upper,
+    // The score is at least this (a move reached beta)
+    Lower,
+    Exact,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TtEntry {
+    pub best_move: Option<Move>,
+    // Mate scores are relative to the position probed
+    pub score: i32,
+    pub depth: u32,
+    pub bound: Bound,
+}
+
+// An entry is the full key and 64 bits of data:
+// move (16 bits), score (16), depth (8), bound (2), generation (6).
+// Threads read and write entries without locking, the key is stored xored with
+// the data so that an entry half written by another thread doesn't match any key.
+#[derive(Debug, Default)]
+struct Slot {
+    key: AtomicU64,
+    data: AtomicU64,
+}
+
+// What a slot held when it was read
+#[derive(Debug, Clone, Copy)]
+struct SlotData {
+    key: u64,
+    data: u64,
+}
+
+const BUCKET_SIZE: usize = 4;
+const GENERATIONS: u8 = 64;
+
+type Bucket = [Slot; BUCKET_SIZE];
+
+pub struct TranspositionTable {
+    buckets: Vec<Bucket>,
+    // Incremented for each search, so that entries of old searches are replaced first
+    generation: u8,
+}
+
+fn pack_move(mv: Option<Move>) -> u64 {
+    let mv = match mv {
+        Some(mv) => mv,
+        None => return 0,
+    };
+    let square = |pos: (usize, usize)| (pos.1 * 8 + pos.0) as u64;
+    let promotion = match mv.promotion {
+        None => 0,
+        Some(PieceType::Knight) => 1,
+        Some(PieceType::Bishop) => 2,
+        Some(PieceType::Rook) => 3,
+        Some(_) => 4,
+    };
+    square(mv.start_pos) | square(mv.end_pos) << 6 | promotion << 12
+}
+
+fn unpack_move(bits: u64) -> Option<Move> {
+    // A move never goes back to its own square, so 0 is free for no move
+    if bits == 0 {
+        return None;
+    }
+    let square = |sq: u64| ((sq & 7) as usize, (sq >> 3 & 7) as usize);
+    let promotion = match bits >> 12 & 7 {
+        1 => Some(PieceType::Knight),
+        2 => Some(PieceType::Bishop),
+        3 => Some(PieceType::Rook),
+        4 => Some(PieceType::Queen),
+        _ => None,
+    };
+    Some(Move { start_pos: square(bits), end_pos: square(bits >> 6), promotion })
+}
+
+// Mate and tablebase scores are stored as distance from the stored position instead of the root
+fn score_to_tt(score: i32, ply: usize) -> i32 {
+    if score >= TB_WIN - MAX_PLY as i32 {
+        score + ply as i32
+    } else if score <= -TB_WIN + MAX_PLY as i32 {
+        score - ply as i32
+    } else {
+        score
+    }
+}
+
+fn score_from_tt(score: i32, ply: usize) -> i32 {
+    if score >= TB_WIN - MAX_PLY as i32 {
+        score - ply as i32
+    } else if score <= -TB_WIN + MAX_PLY as i32 {
+        score + ply as i32
+    } else {
+        score
+    }
+}
+
+impl Slot {
+    fn load(&self) -> SlotData {
+        let data = self.data.load(Ordering::Relaxed);
+        SlotData { key: self.key.load(Ordering::Relaxed) ^ data, data }
+    }
+
+    fn save(&self, key: u64, data: u64) {
+        self.data.store(data, Ordering::Relaxed);
+        self.key.store(key ^ data, Ordering::Relaxed);
+    }
+}
+
+impl SlotData {
+    fn depth(&self) -> u32 {
+        (self.data >> 32 & 0xFF) as u32
+    }
+
+    fn bound(&self) -> Option<Bound> {
+        match self.data >> 40 & 3 {
+            1 => Some(Bound::Upper),
+            2 => Some(Bound::Lower),
+            3 => Some(Bound::Exact),
+            _ => None,
+        }
+    }
+
+    fn generation(&self) -> u8 {
+        (self.data >> 42 & 0x3F) as u8
+    }
+
+    fn is_empty(&self) -> bool {
+        self.bound().is_none()
+    }
+}
+
+impl TranspositionTable {
+    pub fn new(megabytes: usize) -> Self {
+        let mut table = TranspositionTable { buckets: vec![], generation: 0 };
+        table.resize(megabytes);
+        table
+    }
+
+    // Resizing throws the entries away
+    pub fn resize(&mut self, megabytes: usize) {
+        let buckets = (megabytes * 1024 * 1024 / std::mem::size_of::<Bucket>()).max(1);
+        self.buckets = (0..buckets).map(|_| Bucket::default()).collect();
+        self.generation = 0;
+    }
+
+    pub fn clear(&mut self) {
+        self.buckets.iter().flatten().for_each(|slot| slot.save(0, 0));
+        self.generation = 0;
+    }
+
+    pub fn new_search(&mut self) {
+        self.generation = (self.generation + 1) % GENERATIONS;
+    }
+
+    fn bucket_index(&self, key: u64) -> usize {
+        ((key as u128 * self.buckets.len() as u128) >> 64) as usize
+    }
+
+    pub fn probe(&self, key: u64, ply: usize) -> Option<TtEntry> {
+        let slot = self.buckets[self.bucket_index(key)].iter().map(Slot::load).find(|slot| slot.key == key && !slot.is_empty())?;
+        Some(TtEntry {
+            best_move: unpack_move(slot.data & 0xFFFF),
+            score: score_from_tt((slot.data >> 16 & 0xFFFF) as u16 as i16 as i32, ply),
+            depth: slot.depth(),
+            bound: slot.bound()?,
+        })
+    }
+
+    pub fn store(&self, key: u64, depth: u32, bound: Bound, score: i32, ply: usize, best_move: Option<Move>) {
+        let generation = self.generation;
+        let index = self.bucket_index(key);
+        let bucket = &self.buckets[index];
+        let loaded = bucket.each_ref().map(Slot::load);
+
+        // The same position, else the shallowest entry, older searches counting as shallower
+        let age = |slot: &SlotData| (generation + GENERATIONS - slot.generation()) % GENERATIONS;
+        let index = match loaded.iter().position(|slot| slot.key == key && !slot.is_empty()) {
+            Some(i) => {
+                let old = loaded[i];
+                // Keep a deeper result of this search unless the new one is exact
+                if bound != Bound::Exact && age(&old) == 0 && depth + 2 < old.depth() {
+                    return;
+                }
+                i
+            },
+            None => (0..BUCKET_SIZE).min_by_key(|&i| if loaded[i].is_empty() { i32::MIN } else { loaded[i].depth() as i32 - 8 * age(&loaded[i]) as i32 }).unwrap_or(0),
+        };
+        let slot = loaded[index];
+
+        // Don't lose the move of a position when the new result has none
+        let move_bits = match best_move {
+            Some(_) => pack_move(best_move),
+            None if slot.key == key => slot.data & 0xFFFF,
+            None => 0,
+        };
+        let bound_bits = match bound {
+            Bound::Upper => 1,
+            Bound::Lower => 2,
+            Bound::Exact => 3,
+        };
+        let score = score_to_tt(score, ply) as i16 as u16 as u64;
+        bucket[index].save(key, move_bits | score << 16 | (depth.min(255) as u64) << 32 | bound_bits << 40 | (generation as u64) << 42);
+    }
+
+    // Permill of the entries used by the current search, as UCI reports it
+    pub fn hashfull(&self) -> usize {
+        let sample = self.buckets.iter().take(1000 / BUCKET_SIZE).flatten().map(Slot::load);
+        let used = sample.filter(|slot| !slot.is_empty() && slot.generation() == self.generation).count();
+        used * 1000 / (1000 / BUCKET_SIZE * BUCKET_SIZE).min(self.buckets.len() * BUCKET_SIZE)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::search::MATE;
+
+    // A win found 5 plies from the root is 3 plies away from the position, wherever it is reached from
+    #[test]
+    fn distances_are_kept_relative_to_the_position() {
+        let tt = TranspositionTable::new(1);
+        for (key, score) in [(1, TB_WIN - 5), (2, -TB_WIN + 5), (3, MATE - 5), (4, 120)] {
+            tt.store(key, 4, Bound::Exact, score, 2, None);
+            let relative = if score == 120 { score } else { score + score.signum() * 2 };
+            assert_eq!(tt.probe(key, 0).map(|entry| entry.score), Some(relative));
+        }
+    }
+}
@@ -0,0 +1,278 @@
+use std::io::{stdin, BufRead};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::position::{Move, Position, START_FEN};
+use crate::search::{mate_in, Limits, SearchHandle, SearchInfo, SearchOptions, SearchSignals, Searcher, DEFAULT_HASH_MB, MAX_HASH_MB, MAX_THREADS};
+use crate::skill::{self, Skill};
+use crate::tablebase::Tablebase;
+
+const MAX_MULTI_PV: usize = 256;
+
+// "cp 35", or "mate 3" when mating in 3
+fn format_score(score: i32) -> String {
+    match mate_in(score) {
+        Some(moves) => format!("mate {}", moves),
+        None => format!("cp {}", score),
+    }
+}
+
+fn format_moves(moves: &[Move]) -> String {
+    moves.iter().map(Move::to_string).collect::<Vec<_>>().join(" ")
+}
+
+// One info line for each line of the search
+fn print_info(info: &SearchInfo) {
+    for (i, line) in info.lines.iter().enumerate() {
+        println!(
+            "info depth {} multipv {} score {} nodes {} nps {} time {} hashfull {} pv {}",
+            info.depth, i + 1, format_score(line.score), info.nodes, info.nps, info.time.as_millis(), info.hashfull, format_moves(&line.pv)
+        );
+    }
+}
+
+// A search in progress: its signals, and the thread that prints the best move once it is over
+struct Running {
+    signals: Arc<SearchSignals>,
+    thread: JoinHandle<Searcher>,
+}
+
+// The engine as seen by a GUI: the options it was given, the position it was
+// sent, and the search it is running
+struct Engine {
+    // None while a search has it
+    searcher: Option<Searcher>,
+    running: Option<Running>,
+    // The positions of the game, the last one is the one to search
+    positions: Vec<Position>,
+    limit_strength: bool,
+    elo: u32,
+    skill_level: u32,
+}
+
+impl Engine {
+    fn new() -> Self {
+        Engine {
+            searcher: Some(Searcher::new()),
+            running: None,
+            positions: vec![Position::new()],
+            limit_strength: false,
+            elo: skill::MAX_ELO,
+            skill_level: skill::MAX_LEVEL,
+        }
+    }
+
+    // The searcher, once the search that has it is over
+    fn searcher(&mut self) -> &mut Searcher {
+        self.wait();
+        self.searcher.get_or_insert_with(Searcher::new)
+    }
+
+    fn wait(&mut self) {
+        if let Some(running) = self.running.take() {
+            if let Ok(searcher) = running.thread.join() {
+                self.searcher = Some(searcher);
+            }
+        }
+    }
+
+    fn stop(&mut self) {
+        if let Some(running) = &self.running {
+            running.signals.stop();
+        }
+        self.wait();
+    }
+
+    fn print_options() {
+        println!("option name Hash type spin default {} min 1 max {}", DEFAULT_HASH_MB, MAX_HASH_MB);
+        println!("option name Threads type spin default 1 min 1 max {}", MAX_THREADS);
+        println!("option name MultiPV type spin default 1 min 1 max {}", MAX_MULTI_PV);
+        println!("option name Ponder type check default false");
+        println!("option name Clear Hash type button");
+        println!("option name SyzygyPath type string default <empty>");
+        for name in SearchOptions::NAMES {
+            println!("option name {} type check default true", name);
+        }
+        println!("option name UCI_LimitStrength type check default false");
+        println!("option name UCI_Elo type spin default {} min {} max {}", skill::MAX_ELO, skill::MIN_ELO, skill::MAX_ELO);
+        println!("option name Skill Level type spin default {} min 0 max {}", skill::MAX_LEVEL, skill::MAX_LEVEL);
+    }
+
+    // setoption name <name> [value <value>], names can have spaces
+    fn set_option(&mut self, words: &[&str]) {
+        let value_at = words.iter().position(|&word| word == "value").unwrap_or(words.len());
+        let name = words.get(2..value_at).unwrap_or_default().join(" ");
+        let value = words.get(value_at + 1..).unwrap_or_default().join(" ");
+        let number = value.parse::<usize>().ok();
+        let check = match value.as_str() {
+            "true" => Some(true),
+            "false" => Some(false),
+            _ => None,
+        };
+        match (name.as_str(), number, check) {
+            ("Hash", Some(megabytes), _) => self.searcher().set_hash_size(megabytes.clamp(1, MAX_HASH_MB)),
+            ("Threads", Some(threads), _) => self.searcher().set_threads(threads.clamp(1, MAX_THREADS)),
+            ("MultiPV", Some(multi_pv), _) => self.searcher().set_multi_pv(multi_pv.clamp(1, MAX_MULTI_PV)),
+            // Pondering is up to the GUI, which sends go ponder when it is on
+            ("Ponder", _, Some(_)) => (),
+            ("Clear Hash", _, _) => self.searcher().clear_hash(),
+            ("SyzygyPath", _, _) if value.is_empty() || value == "<empty>" => self.searcher().set_tablebase(None),
+            ("SyzygyPath", _, _) => match Tablebase::open(&value) {
+                Ok(tablebase) => {
+                    println!("info string found tablebases up to {} pieces", tablebase.max_pieces());
+                    self.searcher().set_tablebase(Some(Arc::new(tablebase)));
+                },
+                Err(error) => println!("info string can't open tablebases in {}: {}", value, error),
+            },
+            ("UCI_LimitStrength", _, Some(limit_strength)) => {
+                self.limit_strength = limit_strength;
+                self.update_skill();
+            },
+            ("UCI_Elo", Some(elo), _) => {
+                self.elo = elo as u32;
+                self.update_skill();
+            },
+            ("Skill Level", Some(level), _) => {
+                self.skill_level = level as u32;
+                self.update_skill();
+            },
+            (name, _, Some(enabled)) => {
+                let mut options = self.searcher().options();
+                if options.set(name, enabled) {
+                    self.searcher().set_options(options);
+                } else {
+                    println!("info string unknown option {}", name);
+                }
+            },
+            (name, _, _) => println!("info string invalid value for {}", name),
+        }
+    }
+
+    // UCI_Elo when the strength is limited, Skill Level otherwise
+    fn update_skill(&mut self) {
+        let skill = if self.limit_strength { Skill::from_elo(self.elo) } else { Skill::new(self.skill_level) };
+        self.searcher().set_skill(skill);
+    }
+
+    // position startpos|fen <fen> [moves <move>...]
+    fn set_position(&mut self, words: &[&str]) {
+        let moves_at = words.iter().position(|&word| word == "moves").unwrap_or(words.len());
+        let fen = match words.get(1) {
+            Some(&"startpos") => START_FEN.to_string(),
+            Some(&"fen") => words[2..moves_at].join(" "),
+            _ => {
+                println!("info string expected startpos or fen");
+                return;
+            },
+        };
+        let mut position = match Position::from_fen(&fen) {
+            Ok(position) => position,
+            Err(error) => {
+                println!("info string {}", error);
+                return;
+            },
+        };
+        self.positions = vec![position];
+        for text in words.get(moves_at + 1..).unwrap_or_default() {
+            match position.parse_uci(text) {
+                Some(mv) => position.make_move(mv),
+                None => {
+                    println!("info string illegal move {}", text);
+                    return;
+                },
+            }
+            self.positions.push(position);
+        }
+    }
+
+    // go with any of its limits
+    fn go(&mut self, words: &[&str]) {
+        let mut limits = Limits::default();
+        let position = self.positions[self.positions.len() - 1];
+        let mut words = words[1..].iter().peekable();
+        while let Some(&word) = words.next() {
+            // Clocks can be sent negative once they ran out
+            let mut number = || words.next().and_then(|value| value.parse::<i64>().ok()).map(|value| value.max(0) as u64);
+            match word {
+                "wtime" => limits.wtime = number().map(Duration::from_millis),
+                "btime" => limits.btime = number().map(Duration::from_millis),
+                "winc" => limits.winc = Duration::from_millis(number().unwrap_or(0)),
+                "binc" => limits.binc = Duration::from_millis(number().unwrap_or(0)),
+                "movestogo" => limits.movestogo = number().map(|moves| moves as u32),
+                "depth" => limits.depth = number().map(|depth| depth as u32),
+                // A mate in n moves is found within 2n plies
+                "mate" => limits.depth = number().map(|moves| moves as u32 * 2),
+                "nodes" => limits.nodes = number(),
+                "movetime" => limits.movetime = number().map(Duration::from_millis),
+                "infinite" => limits.infinite = true,
+                "ponder" => limits.ponder = true,
+                // Moves up to the first word that isn't one
+                "searchmoves" => {
+                    while let Some(mv) = words.peek().and_then(|text| position.parse_uci(text)) {
+                        words.next();
+                        limits.searchmoves.push(mv);
+                    }
+                },
+                _ => (),
+            }
+        }
+
+        self.wait();
+        let mut searcher = self.searcher.take().unwrap_or_default();
+        let (position, previous) = self.positions.split_last().unwrap_or_else(|| unreachable!());
+        searcher.set_game_history(previous);
+        let handle = SearchHandle::start(searcher, *position, limits, print_info);
+        let signals = handle.signals();
+        let thread = thread::spawn(move || {
+            let (searcher, result) = handle.join();
+            match (result.best_move, result.pv.get(1)) {
+                (Some(best_move), Some(ponder)) => println!("bestmove {} ponder {}", best_move, ponder),
+                (Some(best_move), None) => println!("bestmove {}", best_move),
+                (None, _) => println!("bestmove 0000"),
+            }
+            searcher
+        });
+        self.running = Some(Running { signals, thread });
+    }
+}
+
+// Talks UCI on stdin and stdout until quit
+pub fn run() {
+    let mut engine = Engine::new();
+    for line in stdin().lock().lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(_) => break,
+        };
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.first() {
+            Some(&"uci") => {
+                println!("id name {} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
+                println!("id author Raiigo");
+                Engine::print_options();
+                println!("uciok");
+            },
+            Some(&"isready") => println!("readyok"),
+            Some(&"setoption") => engine.set_option(&words),
+            Some(&"ucinewgame") => engine.searcher().clear_hash(),
+            Some(&"position") => {
+                engine.stop();
+                engine.set_position(&words);
+            },
+            Some(&"go") => {
+                engine.stop();
+                engine.go(&words);
+            },
+            Some(&"stop") => engine.stop(),
+            Some(&"ponderhit") => {
+                if let Some(running) = &engine.running {
+                    running.signals.ponderhit();
+                }
+            },
+            Some(&"quit") => break,
+            _ => (),
+        }
+    }
+    engine.stop();
+}
@@ -0,0 +1,530 @@
+use std::fmt::{self, Display};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::position::{Move, Position};
+use crate::search::{Limits, MATE};
+
+// How long an engine has to answer uci and isready
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug)]
+pub enum UciError {
+    Io(io::Error),
+    // The engine closed its output, it most likely exited
+    Closed,
+    Timeout,
+    // The engine sent something that does not make sense here
+    Protocol(String),
+}
+
+impl Display for UciError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UciError::Io(error) => write!(f, "{}", error),
+            UciError::Closed => write!(f, "the engine exited"),
+            UciError::Timeout => write!(f, "the engine did not answer in time"),
+            UciError::Protocol(message) => write!(f, "protocol error: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for UciError {}
+
+impl From<io::Error> for UciError {
+    fn from(error: io::Error) -> Self {
+        UciError::Io(error)
+    }
+}
+
+// An option the engine announced, as in "option name Hash type spin default 16 min 1 max 1024"
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct EngineOption {
+    pub name: String,
+    pub kind: String,
+    pub default: Option<String>,
+    pub min: Option<i64>,
+    pub max: Option<i64>,
+    // Values of a combo
+    pub vars: Vec<String>,
+}
+
+impl EngineOption {
+    fn parse(line: &str) -> Option<Self> {
+        let mut option = EngineOption::default();
+        let mut field = "";
+        let mut value: Vec<&str> = vec![];
+        fn set(option: &mut EngineOption, field: &str, value: &[&str]) {
+            let value = value.join(" ");
+            match field {
+                "name" => option.name = value,
+                "type" => option.kind = value,
+                "default" => option.default = Some(value),
+                "min" => option.min = value.parse().ok(),
+                "max" => option.max = value.parse().ok(),
+                "var" => option.vars.push(value),
+                _ => (),
+            }
+        }
+        // A field goes on until the next keyword, names can have spaces
+        for word in line.split_whitespace().skip(1) {
+            if matches!(word, "name" | "type" | "default" | "min" | "max" | "var") {
+                set(&mut option, field, &value);
+                field = word;
+                value.clear();
+            } else {
+                value.push(word);
+            }
+        }
+        set(&mut option, field, &value);
+        Some(option).filter(|option| !option.name.is_empty())
+    }
+}
+
+// Progress reported by the engine in an info line, moves are checked against the position searched
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct EngineInfo {
+    pub depth: Option<u32>,
+    // Our scale: centipawns, MATE - n when mating in n plies
+    pub score: Option<i32>,
+    pub multi_pv: usize,
+    pub nodes: Option<u64>,
+    pub nps: Option<u64>,
+    pub time: Option<Duration>,
+    pub pv: Vec<Move>,
+    pub string: Option<String>,
+}
+
+impl EngineInfo {
+    fn parse(line: &str, position: &Position) -> Self {
+        let mut info = EngineInfo { multi_pv: 1, ..EngineInfo::default() };
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let number = |i: usize| words.get(i + 1).and_then(|word| word.parse::<i64>().ok());
+        let mut i = 1;
+        while i < words.len() {
+            match words[i] {
+                "depth" => info.depth = number(i).map(|depth| depth as u32),
+                "multipv" => info.multi_pv = number(i).unwrap_or(1) as usize,
+                "nodes" => info.nodes = number(i).map(|nodes| nodes as u64),
+                "nps" => info.nps = number(i).map(|nps| nps as u64),
+                "time" => info.time = number(i).map(|millis| Duration::from_millis(millis.max(0) as u64)),
+                "score" => {
+                    info.score = match (words.get(i + 1), number(i + 1)) {
+                        (Some(&"cp"), Some(cp)) => Some(cp as i32),
+                        (Some(&"mate"), Some(moves)) if moves > 0 => Some(MATE - (2 * moves as i32 - 1)),
+                        (Some(&"mate"), Some(moves)) => Some(-MATE + 2 * moves.unsigned_abs() as i32),
+                        _ => None,
+                    };
+                    i += 1;
+                },
+                "pv" => {
+                    let mut next = *position;
+                    for text in &words[i + 1..] {
+                        match next.parse_uci(text) {
+                            Some(mv) => {
+                                info.pv.push(mv);
+                                next.make_move(mv);
+                            },
+                            None => break,
+                        }
+                    }
+                    break;
+                },
+                "string" => {
+                    info.string = Some(words[i + 1..].join(" "));
+                    break;
+                },
+                _ => {
+                    i += 1;
+                    continue;
+                },
+            }
+            i += 2;
+        }
+        info
+    }
+}
+
+// The answer to a go: the move to play, the reply the engine expects, and the
+// last info it sent about the main line
+#[derive(Debug, Clone, PartialEq)]
+pub struct BestMove {
+    pub best_move: Option<Move>,
+    pub ponder: Option<Move>,
+    pub info: EngineInfo,
+}
+
+// An engine in another process, or anything else speaking UCI on a pair of
+// streams, driven through the protocol
+pub struct UciEngine {
+    child: Option<Child>,
+    writer: Box<dyn Write + Send>,
+    // Lines from the engine, read on their own thread so that reads can time out
+    lines: Receiver<String>,
+    pub name: String,
+    pub author: String,
+    pub options: Vec<EngineOption>,
+    // The position last sent, to read the moves of the engine
+    position: Position,
+}
+
+impl UciEngine {
+    // Starts the engine and goes through the handshake
+    pub fn spawn(program: &str, args: &[&str]) -> Result<Self, UciError> {
+        let mut child = Command::new(program).args(args).stdin(Stdio::piped()).stdout(Stdio::piped()).stderr(Stdio::null()).spawn()?;
+        let (stdin, stdout) = match (child.stdin.take(), child.stdout.take()) {
+            (Some(stdin), Some(stdout)) => (stdin, stdout),
+            _ => return Err(UciError::Closed),
+        };
+        // Dropping the engine kills the process when the handshake fails
+        let mut engine = Self::connect(stdout, stdin);
+        engine.child = Some(child);
+        engine.handshake()?;
+        Ok(engine)
+    }
+
+    // An engine behind any reader and writer, such as a scripted stub
+    pub fn from_streams(reader: impl Read + Send + 'static, writer: impl Write + Send + 'static) -> Result<Self, UciError> {
+        let mut engine = Self::connect(reader, writer);
+        engine.handshake()?;
+        Ok(engine)
+    }
+
+    fn connect(reader: impl Read + Send + 'static, writer: impl Write + Send + 'static) -> Self {
+        let (sender, lines) = mpsc::channel();
+        thread::spawn(move || {
+            for line in BufReader::new(reader).lines() {
+                let Ok(line) = line else {
+                    return;
+                };
+                if sender.send(line).is_err() {
+                    return;
+                }
+            }
+        });
+        UciEngine { child: None, writer: Box::new(writer), lines, name: String::new(), author: String::new(), options: vec![], position: Position::new() }
+    }
+
+    fn handshake(&mut self) -> Result<(), UciError> {
+        self.send("uci")?;
+        let deadline = Instant::now() + HANDSHAKE_TIMEOUT;
+        loop {
+            let line = self.read_line(Some(deadline))?;
+            let (command, rest) = line.trim().split_once(' ').unwrap_or((line.trim(), ""));
+            match command {
+                "uciok" => return self.is_ready(),
+                "id" => match rest.split_once(' ') {
+                    Some(("name", name)) => self.name = name.to_string(),
+                    Some(("author", author)) => self.author = author.to_string(),
+                    _ => (),
+                },
+                "option" => self.options.extend(EngineOption::parse(&line)),
+                _ => (),
+            }
+        }
+    }
+
+    pub fn send(&mut self, line: &str) -> Result<(), UciError> {
+        writeln!(self.writer, "{}", line)?;
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    // The next line from the engine, waiting until the deadline if there is one
+    fn read_line(&mut self, deadline: Option<Instant>) -> Result<String, UciError> {
+        match deadline {
+            Some(deadline) => match self.lines.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+                Ok(line) => Ok(line),
+                Err(RecvTimeoutError::Timeout) => Err(UciError::Timeout),
+                Err(RecvTimeoutError::Disconnected) => Err(UciError::Closed),
+            },
+            None => self.lines.recv().map_err(|_| UciError::Closed),
+        }
+    }
+
+    pub fn is_ready(&mut self) -> Result<(), UciError> {
+        self.send("isready")?;
+        let deadline = Instant::now() + HANDSHAKE_TIMEOUT;
+        while self.read_line(Some(deadline))?.trim() != "readyok" {}
+        Ok(())
+    }
+
+    pub fn set_option(&mut self, name: &str, value: &str) -> Result<(), UciError> {
+        if !self.options.iter().any(|option| option.name.eq_ignore_ascii_case(name)) {
+            return Err(UciError::Protocol(format!("{} has no option {}", self.name, name)));
+        }
+        self.send(&format!("setoption name {} value {}", name, value))
+    }
+
+    pub fn new_game(&mut self) -> Result<(), UciError> {
+        self.send("ucinewgame")?;
+        self.is_ready()
+    }
+
+    // The game from its start, the engine searches the position after the moves
+    pub fn set_position(&mut self, start: &Position, moves: &[Move]) -> Result<(), UciError> {
+        let mut command = format!("position fen {}", start.to_fen());
+        if !moves.is_empty() {
+            command.push_str(" moves");
+        }
+        let mut position = *start;
+        for &mv in moves {
+            command.push_str(&format!(" {}", mv));
+            position.make_move(mv);
+        }
+        self.position = position;
+        self.send(&command)
+    }
+
+    // Starts a search of the position sent, the answer comes from wait_best_move
+    pub fn go(&mut self, limits: &Limits) -> Result<(), UciError> {
+        let mut command = String::from("go");
+        let millis = |duration: Duration| duration.as_millis();
+        if limits.ponder {
+            command.push_str(" ponder");
+        }
+        if let Some(wtime) = limits.wtime {
+            command.push_str(&format!(" wtime {} winc {}", millis(wtime), millis(limits.winc)));
+        }
+        if let Some(btime) = limits.btime {
+            command.push_str(&format!(" btime {} binc {}", millis(btime), millis(limits.binc)));
+        }
+        if let Some(movestogo) = limits.movestogo {
+            command.push_str(&format!(" movestogo {}", movestogo));
+        }
+        if let Some(depth) = limits.depth {
+            command.push_str(&format!(" depth {}", depth));
+        }
+        if let Some(nodes) = limits.nodes {
+            command.push_str(&format!(" nodes {}", nodes));
+        }
+        if let Some(movetime) = limits.movetime {
+            command.push_str(&format!(" movetime {}", millis(movetime)));
+        }
+        if limits.infinite {
+            command.push_str(" infinite");
+        }
+        // Last, since the moves go on until the end of the line
+        if !limits.searchmoves.is_empty() {
+            command.push_str(" searchmoves");
+            for mv in &limits.searchmoves {
+                command.push_str(&format!(" {}", mv));
+            }
+        }
+        self.send(&command)
+    }
+
+    pub fn stop(&mut self) -> Result<(), UciError> {
+        self.send("stop")
+    }
+
+    pub fn ponderhit(&mut self) -> Result<(), UciError> {
+        self.send("ponderhit")
+    }
+
+    // Reads the engine's info lines until its bestmove, giving up at the timeout
+    pub fn wait_best_move(&mut self, timeout: Option<Duration>, mut on_info: impl FnMut(&EngineInfo)) -> Result<BestMove, UciError> {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        let mut last = EngineInfo::default();
+        loop {
+            let line = self.read_line(deadline)?;
+            let words: Vec<&str> = line.split_whitespace().collect();
+            match words.first() {
+                Some(&"info") => {
+                    let info = EngineInfo::parse(&line, &self.position);
+                    if info.multi_pv == 1 && !info.pv.is_empty() {
+                        last = info.clone();
+                    }
+                    on_info(&info);
+                },
+                Some(&"bestmove") => {
+                    let best_move = match words.get(1) {
+                        Some(&"0000") | Some(&"(none)") | None => None,
+                        Some(text) => Some(self.position.parse_uci(text).ok_or_else(|| UciError::Protocol(format!("illegal best move {}", text)))?),
+                    };
+                    let ponder = match (best_move, words.get(2), words.get(3)) {
+                        (Some(best_move), Some(&"ponder"), Some(text)) => {
+                            let mut next = self.position;
+                            next.make_move(best_move);
+                            next.parse_uci(text)
+                        },
+                        _ => None,
+                    };
+                    return Ok(BestMove { best_move, ponder, info: last });
+                },
+                _ => (),
+            }
+        }
+    }
+
+    // Searches the position sent within the limits and waits for the answer
+    pub fn search(&mut self, limits: &Limits) -> Result<BestMove, UciError> {
+        self.go(limits)?;
+        self.wait_best_move(None, |_| ())
+    }
+
+    // Asks the engine to quit, and kills it if it is still there after a second
+    pub fn quit(mut self) {
+        let _ = self.send("quit");
+        if let Some(mut child) = self.child.take() {
+            let deadline = Instant::now() + Duration::from_secs(1);
+            while Instant::now() < deadline {
+                if let Ok(Some(_)) = child.try_wait() {
+                    return;
+                }
+                thread::sleep(Duration::from_millis(10));
+            }
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+}
+
+impl Drop for UciEngine {
+    fn drop(&mut self) {
+        if let Some(mut child) = self.child.take() {
+            let _ = self.send("quit");
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use std::sync::{Arc, Mutex};
+
+    const HANDSHAKE: &str = "id name Stub Engine 1.0\nid author Someone\n\
+option name Clear Hash type button\n\
+option name Skill Level type spin default 20 min 0 max 20\n\
+option name Style type combo default Normal var Solid var Normal var Risky\n\
+uciok\nreadyok\n";
+
+    // Answers with the script, then stays silent without closing, like a hung engine
+    struct Stub(Cursor<String>);
+
+    impl Read for Stub {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            match self.0.read(buf)? {
+                0 => loop {
+                    thread::park();
+                },
+                read => Ok(read),
+            }
+        }
+    }
+
+    // What the client sent to the stub, shared with the test
+    #[derive(Clone, Default)]
+    struct Sent(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Sent {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().map_err(|_| io::Error::other("poisoned"))?.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Sent {
+        fn lines(&self) -> Vec<String> {
+            let sent = self.0.lock().map(|sent| sent.clone()).unwrap_or_default();
+            String::from_utf8_lossy(&sent).lines().map(str::to_string).collect()
+        }
+    }
+
+    fn stub(script: &str) -> (UciEngine, Sent) {
+        let sent = Sent::default();
+        let engine = UciEngine::from_streams(Stub(Cursor::new(format!("{}{}", HANDSHAKE, script))), sent.clone());
+        (engine.unwrap_or_else(|error| panic!("handshake failed: {}", error)), sent)
+    }
+
+    fn uci_move(position: &Position, text: &str) -> Move {
+        position.parse_uci(text).unwrap_or_else(|| panic!("illegal move {}", text))
+    }
+
+    #[test]
+    fn handshake_reads_id_and_options() {
+        let (engine, _) = stub("");
+        assert_eq!(engine.name, "Stub Engine 1.0");
+        assert_eq!(engine.author, "Someone");
+        let names: Vec<&str> = engine.options.iter().map(|option| option.name.as_str()).collect();
+        assert_eq!(names, ["Clear Hash", "Skill Level", "Style"]);
+        assert_eq!((engine.options[1].min, engine.options[1].max), (Some(0), Some(20)));
+        assert_eq!(engine.options[2].vars, ["Solid", "Normal", "Risky"]);
+    }
+
+    #[test]
+    fn option_names_with_spaces() {
+        let option = EngineOption::parse("option name UCI Chess960 Mode type check default false");
+        assert_eq!(option.map(|option| (option.name, option.kind, option.default)), Some(("UCI Chess960 Mode".to_string(), "check".to_string(), Some("false".to_string()))));
+    }
+
+    #[test]
+    fn mate_scores_in_plies() {
+        let position = Position::new();
+        assert_eq!(EngineInfo::parse("info depth 9 score mate 3", &position).score, Some(MATE - 5));
+        assert_eq!(EngineInfo::parse("info depth 9 score mate -2", &position).score, Some(-MATE + 4));
+        assert_eq!(EngineInfo::parse("info depth 9 score cp -35", &position).score, Some(-35));
+    }
+
+    #[test]
+    fn best_move_with_ponder_and_info() {
+        let (mut engine, _) = stub("info depth 4 score mate -2 nodes 1200 pv e2e4 e7e5 g1f3\nbestmove e2e4 ponder e7e5\n");
+        let start = Position::new();
+        engine.set_position(&start, &[]).unwrap_or_else(|error| panic!("{}", error));
+        let answer = engine.wait_best_move(Some(Duration::from_secs(5)), |_| ()).unwrap_or_else(|error| panic!("{}", error));
+        let e4 = uci_move(&start, "e2e4");
+        let mut after = start;
+        after.make_move(e4);
+        assert_eq!(answer.best_move, Some(e4));
+        assert_eq!(answer.ponder, Some(uci_move(&after, "e7e5")));
+        assert_eq!(answer.info.score, Some(-MATE + 4));
+        assert_eq!(answer.info.nodes, Some(1200));
+        assert_eq!(answer.info.pv.len(), 3);
+    }
+
+    #[test]
+    fn illegal_best_move_is_a_protocol_error() {
+        let (mut engine, _) = stub("bestmove e2e5\n");
+        let answer = engine.wait_best_move(Some(Duration::from_secs(5)), |_| ());
+        assert!(matches!(answer, Err(UciError::Protocol(_))), "{:?}", answer);
+    }
+
+    #[test]
+    fn silent_engine_times_out() {
+        let (mut engine, _) = stub("info depth 1 score cp 20 pv e2e4\n");
+        let answer = engine.wait_best_move(Some(Duration::from_millis(50)), |_| ());
+        assert!(matches!(answer, Err(UciError::Timeout)), "{:?}", answer);
+    }
+
+    #[test]
+    fn commands_sent_to_the_engine() {
+        let (mut engine, sent) = stub("");
+        let start = Position::new();
+        let e4 = uci_move(&start, "e2e4");
+        let mut after = start;
+        after.make_move(e4);
+        let searchmoves = vec![uci_move(&after, "e7e5"), uci_move(&after, "c7c5")];
+        let limits = Limits { wtime: Some(Duration::from_secs(60)), winc: Duration::from_secs(1), btime: Some(Duration::from_secs(50)), depth: Some(8), searchmoves, ..Limits::default() };
+        engine.set_option("Skill Level", "3").unwrap_or_else(|error| panic!("{}", error));
+        assert!(engine.set_option("Contempt", "10").is_err());
+        engine.set_position(&start, &[e4]).unwrap_or_else(|error| panic!("{}", error));
+        engine.go(&limits).unwrap_or_else(|error| panic!("{}", error));
+        assert_eq!(sent.lines(), [
+            "uci",
+            "isready",
+            "setoption name Skill Level value 3",
+            &format!("position fen {} moves e2e4", start.to_fen()),
+            "go wtime 60000 winc 1000 btime 50000 binc 0 depth 8 searchmoves e7e5 c7c5",
+        ]);
+    }
+}
@@ -0,0 +1,399 @@
+use std::io::{stdin, BufRead};
+use std::sync::mpsc::{self, Sender};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::outcome::outcome;
+use crate::position::{Move, Position};
+use crate::search::{mate_in, Limits, SearchHandle, SearchInfo, SearchResult, SearchSignals, Searcher, MAX_HASH_MB, MAX_THREADS};
+use crate::tablebase::Tablebase;
+use crate::Color;
+
+// Variants the engine plays, as announced to the interface
+const VARIANTS: &[&str] = &["normal"];
+
+// What the main loop waits for: a line from the interface, or the end of a search
+enum Event {
+    Command(String),
+    Done(u64, SearchResult),
+}
+
+// Scores as CECP wants them, mates as 100000 plus the number of moves
+fn format_score(score: i32) -> i32 {
+    match mate_in(score) {
+        Some(moves) if moves > 0 => 100000 + moves,
+        Some(moves) => -100000 + moves,
+        None => score,
+    }
+}
+
+// Thinking output: ply, score, time in centiseconds, nodes and the principal variation
+fn print_thinking(position: &Position, info: &SearchInfo) {
+    let mut next = *position;
+    let mut pv = vec![];
+    for &mv in &info.pv {
+        pv.push(next.san(mv));
+        next.make_move(mv);
+    }
+    println!("{} {} {} {} {}", info.depth, format_score(info.score), info.time.as_millis() / 10, info.nodes, pv.join(" "));
+}
+
+// "5" minutes or "0:30" minutes and seconds, as in the level command
+fn parse_base(text: &str) -> Option<Duration> {
+    let (minutes, seconds) = text.split_once(':').unwrap_or((text, "0"));
+    Some(Duration::from_secs(minutes.parse::<u64>().ok()? * 60 + seconds.parse::<u64>().ok()?))
+}
+
+fn parse_seconds(text: &str) -> Option<Duration> {
+    text.parse::<f64>().ok().filter(|seconds| seconds.is_finite() && *seconds >= 0.0).map(Duration::from_secs_f64)
+}
+
+// A clock in centiseconds, as in the time and otim commands
+fn parse_centiseconds(text: &str) -> Option<Duration> {
+    text.parse::<i64>().ok().map(|centiseconds| Duration::from_millis(centiseconds.max(0) as u64 * 10))
+}
+
+// A search running for the engine
+struct Thinking {
+    id: u64,
+    signals: Arc<SearchSignals>,
+    thread: JoinHandle<Searcher>,
+    // The reply a ponder search expects, until the opponent plays it
+    ponder_move: Option<Move>,
+}
+
+struct Engine {
+    events: Sender<Event>,
+    // None while a search has it
+    searcher: Option<Searcher>,
+    thinking: Option<Thinking>,
+    searches: u64,
+    // Every position of the game, for undo and repetitions
+    positions: Vec<Position>,
+    // The side the engine plays, None in force mode
+    engine_color: Option<Color>,
+    post: bool,
+    ponder: bool,
+    // level: moves per session, 0 for the whole game, base time and increment
+    moves_per_session: u32,
+    base: Duration,
+    increment: Duration,
+    // st and sd
+    move_time: Option<Duration>,
+    depth: Option<u32>,
+    own_time: Duration,
+    opponent_time: Duration,
+}
+
+impl Engine {
+    fn new(events: Sender<Event>) -> Self {
+        let base = Duration::from_secs(5 * 60);
+        Engine {
+            events,
+            searcher: Some(Searcher::new()),
+            thinking: None,
+            searches: 0,
+            positions: vec![Position::new()],
+            engine_color: Some(Color::Black),
+            post: false,
+            ponder: false,
+            moves_per_session: 40,
+            base,
+            increment: Duration::ZERO,
+            move_time: None,
+            depth: None,
+            own_time: base,
+            opponent_time: base,
+        }
+    }
+
+    fn position(&self) -> Position {
+        self.positions[self.positions.len() - 1]
+    }
+
+    // The searcher, once the search that has it is over
+    fn searcher(&mut self) -> &mut Searcher {
+        self.stop();
+        self.searcher.get_or_insert_with(Searcher::new)
+    }
+
+    // Abandons the search in progress, its move is not played
+    fn stop(&mut self) {
+        if let Some(thinking) = self.thinking.take() {
+            thinking.signals.stop();
+            if let Ok(searcher) = thinking.thread.join() {
+                self.searcher = Some(searcher);
+            }
+        }
+    }
+
+    fn features() {
+        let variants = VARIANTS.join(",");
+        println!("feature myname=\"{} {}\" variants=\"{}\"", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"), variants);
+        println!("feature ping=1 setboard=1 usermove=1 playother=1 san=0 time=1 draw=0 sigint=0 sigterm=0 reuse=1 analyze=0 colors=0 memory=1 smp=1 egt=\"syzygy\" done=1");
+    }
+
+    // The limits for the engine's move from the time control, with the clock
+    // the interface last sent
+    fn limits(&self) -> Limits {
+        let mut limits = match self.move_time {
+            Some(move_time) => Limits::movetime(move_time),
+            None => {
+                let color = self.engine_color.unwrap_or(self.position().side_to_move);
+                // Moves the engine played in the current session
+                let played = self.positions[..self.positions.len() - 1].iter().filter(|position| position.side_to_move == color).count() as u32;
+                let movestogo = (self.moves_per_session > 0).then(|| self.moves_per_session - played % self.moves_per_session);
+                let (wtime, btime) = match color {
+                    Color::White => (self.own_time, self.opponent_time),
+                    Color::Black => (self.opponent_time, self.own_time),
+                };
+                Limits { wtime: Some(wtime), btime: Some(btime), winc: self.increment, binc: self.increment, movestogo, ..Limits::default() }
+            },
+        };
+        limits.depth = self.depth;
+        limits
+    }
+
+    // Starts a search for the engine's move, or for its move after ponder_move
+    fn start(&mut self, ponder_move: Option<Move>) {
+        self.stop();
+        let mut searcher = self.searcher.take().unwrap_or_default();
+        let mut positions = self.positions.clone();
+        let mut limits = self.limits();
+        if let Some(ponder_move) = ponder_move {
+            let mut next = self.position();
+            next.make_move(ponder_move);
+            positions.push(next);
+            limits.ponder = true;
+        }
+        let (position, previous) = positions.split_last().unwrap_or_else(|| unreachable!());
+        let position = *position;
+        searcher.set_game_history(previous);
+
+        self.searches += 1;
+        let (id, events, post) = (self.searches, self.events.clone(), self.post);
+        let handle = SearchHandle::start(searcher, position, limits, move |info| {
+            if post {
+                print_thinking(&position, info);
+            }
+        });
+        let signals = handle.signals();
+        let thread = thread::spawn(move || {
+            let (searcher, result) = handle.join();
+            let _ = events.send(Event::Done(id, result));
+            searcher
+        });
+        self.thinking = Some(Thinking { id, signals, thread, ponder_move });
+    }
+
+    // Thinks when it is the engine's turn in a game that is not over
+    fn think(&mut self) {
+        if self.engine_color == Some(self.position().side_to_move) && outcome(&self.positions).is_none() {
+            self.start(None);
+        }
+    }
+
+    // Prints the result when the game is over
+    fn report(&self) -> bool {
+        match outcome(&self.positions) {
+            Some(outcome) => {
+                println!("{} {{{}}}", outcome.result(), outcome);
+                true
+            },
+            None => false,
+        }
+    }
+
+    // A search ended: plays its move, then ponders on the reply it expects
+    fn done(&mut self, id: u64, result: SearchResult) {
+        // Searches that were stopped to be thrown away still report
+        if self.thinking.as_ref().is_none_or(|thinking| thinking.id != id) {
+            return;
+        }
+        if let Some(thinking) = self.thinking.take() {
+            if let Ok(searcher) = thinking.thread.join() {
+                self.searcher = Some(searcher);
+            }
+        }
+        let mv = match result.best_move {
+            Some(mv) => mv,
+            None => return,
+        };
+        println!("move {}", mv);
+        let mut next = self.position();
+        next.make_move(mv);
+        self.positions.push(next);
+        if self.report() {
+            return;
+        }
+        if let Some(&reply) = result.pv.get(1).filter(|_| self.ponder) {
+            self.start(Some(reply));
+        }
+    }
+
+    fn user_move(&mut self, text: &str) {
+        let position = self.position();
+        let mv = match position.parse_uci(text).or_else(|| position.parse_san(text)) {
+            Some(mv) => mv,
+            None => {
+                println!("Illegal move: {}", text);
+                return;
+            },
+        };
+        let ponder_move = self.thinking.as_ref().and_then(|thinking| thinking.ponder_move);
+        if ponder_move != Some(mv) {
+            self.stop();
+        }
+        let mut next = position;
+        next.make_move(mv);
+        self.positions.push(next);
+        if self.report() {
+            self.stop();
+            return;
+        }
+        match &mut self.thinking {
+            // The search on the expected reply goes on, now on the engine's clock
+            Some(thinking) => {
+                thinking.ponder_move = None;
+                thinking.signals.ponderhit();
+            },
+            None => self.think(),
+        }
+    }
+
+    fn undo(&mut self, moves: usize) {
+        self.stop();
+        let kept = self.positions.len().saturating_sub(moves).max(1);
+        self.positions.truncate(kept);
+    }
+
+    fn command(&mut self, line: &str) -> bool {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let argument = words.get(1).copied().unwrap_or("");
+        match words.first().copied() {
+            None | Some("xboard") | Some("accepted") | Some("rejected") | Some("random") | Some("computer") | Some("name") | Some("rating") | Some("ics") | Some("draw") | Some("nps") => (),
+            Some("protover") => Self::features(),
+            Some("quit") => return false,
+            Some("new") => {
+                self.stop();
+                self.positions = vec![Position::new()];
+                self.engine_color = Some(Color::Black);
+                self.depth = None;
+                self.move_time = None;
+                self.searcher().clear_hash();
+            },
+            Some("variant") => {
+                if !VARIANTS.contains(&argument) {
+                    println!("Error (unsupported variant): {}", argument);
+                }
+            },
+            Some("force") | Some("result") => {
+                self.stop();
+                self.engine_color = None;
+            },
+            Some("go") => {
+                self.engine_color = Some(self.position().side_to_move);
+                self.think();
+            },
+            Some("playother") => {
+                self.stop();
+                self.engine_color = Some(self.position().side_to_move.opponent());
+            },
+            Some("level") => match (words.get(1).and_then(|moves| moves.parse().ok()), words.get(2).and_then(|base| parse_base(base)), words.get(3).and_then(|increment| parse_seconds(increment))) {
+                (Some(moves_per_session), Some(base), Some(increment)) => {
+                    self.moves_per_session = moves_per_session;
+                    self.base = base;
+                    self.increment = increment;
+                    self.own_time = base;
+                    self.opponent_time = base;
+                    self.move_time = None;
+                },
+                _ => println!("Error (bad level): {}", line),
+            },
+            Some("st") => match parse_seconds(argument) {
+                Some(move_time) => self.move_time = Some(move_time),
+                None => println!("Error (bad time): {}", argument),
+            },
+            Some("sd") => match argument.parse::<u32>() {
+                Ok(depth) if depth > 0 => self.depth = Some(depth),
+                _ => println!("Error (bad depth): {}", argument),
+            },
+            Some("time") => self.own_time = parse_centiseconds(argument).unwrap_or(self.own_time),
+            Some("otim") => self.opponent_time = parse_centiseconds(argument).unwrap_or(self.opponent_time),
+            Some("usermove") => self.user_move(argument),
+            // Move now
+            Some("?") => {
+                if let Some(thinking) = self.thinking.as_ref().filter(|thinking| thinking.ponder_move.is_none()) {
+                    thinking.signals.stop();
+                }
+            },
+            Some("ping") => println!("pong {}", argument),
+            Some("setboard") => match Position::from_fen(&words[1..].join(" ")) {
+                Ok(position) => {
+                    self.stop();
+                    self.positions = vec![position];
+                },
+                Err(error) => println!("tellusererror Illegal position: {}", error),
+            },
+            Some("undo") => self.undo(1),
+            Some("remove") => self.undo(2),
+            Some("hard") => self.ponder = true,
+            Some("easy") => {
+                self.ponder = false;
+                if self.thinking.as_ref().is_some_and(|thinking| thinking.ponder_move.is_some()) {
+                    self.stop();
+                }
+            },
+            Some("post") => self.post = true,
+            Some("nopost") => self.post = false,
+            Some("memory") => match argument.parse::<usize>() {
+                Ok(megabytes) => self.searcher().set_hash_size(megabytes.clamp(1, MAX_HASH_MB)),
+                Err(_) => println!("Error (bad memory): {}", argument),
+            },
+            Some("cores") => match argument.parse::<usize>() {
+                Ok(cores) => self.searcher().set_threads(cores.clamp(1, MAX_THREADS)),
+                Err(_) => println!("Error (bad cores): {}", argument),
+            },
+            Some("egtpath") => match (argument, words.get(2..).map(|path| path.join(" "))) {
+                ("syzygy", Some(path)) => match Tablebase::open(&path) {
+                    Ok(tablebase) => self.searcher().set_tablebase(Some(Arc::new(tablebase))),
+                    Err(error) => println!("Error (bad egtpath): {}", error),
+                },
+                _ => println!("Error (unsupported egtpath): {}", argument),
+            },
+            // Moves without usermove, from interfaces that ignored the feature
+            Some(word) if self.position().parse_uci(word).is_some() => self.user_move(word),
+            Some(word) => println!("Error (unknown command): {}", word),
+        }
+        true
+    }
+}
+
+// Talks CECP v2 on stdin and stdout until quit
+pub fn run() {
+    let (events, receiver) = mpsc::channel();
+    let commands = events.clone();
+    thread::spawn(move || {
+        for line in stdin().lock().lines() {
+            let line = line.unwrap_or_else(|_| "quit".to_string());
+            if commands.send(Event::Command(line)).is_err() {
+                return;
+            }
+        }
+        let _ = commands.send(Event::Command("quit".to_string()));
+    });
+
+    let mut engine = Engine::new(events);
+    for event in receiver {
+        match event {
+            Event::Command(line) => {
+                if !engine.command(&line) {
+                    break;
+                }
+            },
+            Event::Done(id, result) => engine.done(id, result),
+        }
+    }
+    engine.stop();
+}